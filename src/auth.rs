@@ -0,0 +1,356 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordVerifier};
+use chrono::prelude::*;
+use chrono::Duration;
+use futures_util::future::{ready, Either, Ready};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+use crate::error::{ApiResult, Error};
+use crate::users::{Role, USER_LIST};
+
+#[derive(Deserialize)]
+struct Login {
+    name: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct Token {
+    token: String,
+    user_id: u32,
+    expires_at: String,
+}
+
+struct Session {
+    user_id: u32,
+    expires_at: DateTime<Utc>,
+}
+
+lazy_static! {
+    /// Login sessions by token
+    static ref SESSIONS: Arc<Mutex<HashMap<String, Session>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Hash a password into a PHC string with a random salt
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Exchange a user name and password for a bearer token
+#[post("/login")]
+async fn login(body: web::Json<Login>, config: web::Data<Config>) -> ApiResult<HttpResponse> {
+    let lock = USER_LIST.lock().unwrap();
+    let user = lock
+        .iter()
+        .find(|x| x.name == body.name)
+        .map(|x| (x.id.unwrap(), x.password.clone(), x.disabled));
+    drop(lock);
+    // Hashing is slow, so verify without holding the user list
+    let user_id = match user {
+        Some((user_id, Some(hash), disabled)) if verify_password(&body.password, &hash) => {
+            if disabled {
+                return Err(Error::Forbidden(format!("User {} is disabled.", user_id)));
+            }
+            user_id
+        }
+        _ => {
+            return Err(Error::Unauthorized(
+                "Wrong user name or password.".to_string(),
+            ))
+        }
+    };
+
+    let mut bytes = [0_u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|x| format!("{:02x}", x)).collect();
+    let expires_at = Utc::now() + Duration::seconds(config.auth.token_lifetime);
+
+    let mut lock = SESSIONS.lock().unwrap();
+    let now = Utc::now();
+    lock.retain(|_, x| x.expires_at > now);
+    lock.insert(
+        token.clone(),
+        Session {
+            user_id,
+            expires_at,
+        },
+    );
+    drop(lock);
+
+    Ok(HttpResponse::Ok().json(Token {
+        token,
+        user_id,
+        expires_at: expires_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+    }))
+}
+
+/// Invalidate the token of the request
+#[post("/logout")]
+async fn logout(req: HttpRequest) -> ApiResult<HttpResponse> {
+    let token = match bearer(&req)? {
+        Some(token) => token,
+        None => return Err(Error::Unauthorized("Missing token.".to_string())),
+    };
+    SESSIONS.lock().unwrap().remove(&token);
+    Ok(HttpResponse::Ok().json(serde_json::json!({})))
+}
+
+/// The token in the `Authorization: Bearer` header, if any
+fn bearer(req: &HttpRequest) -> ApiResult<Option<String>> {
+    let header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    match header.to_str().ok().and_then(|x| x.strip_prefix("Bearer ")) {
+        Some(token) => Ok(Some(token.trim().to_string())),
+        None => Err(Error::Unauthorized(
+            "Malformed Authorization header.".to_string(),
+        )),
+    }
+}
+
+/// The user logged in with the token of the request, if any
+fn token_user(req: &HttpRequest) -> ApiResult<Option<u32>> {
+    let token = match bearer(req)? {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    let lock = SESSIONS.lock().unwrap();
+    match lock.get(&token) {
+        Some(session) if session.expires_at > Utc::now() => Ok(Some(session.user_id)),
+        _ => Err(Error::Unauthorized("Invalid or expired token.".to_string())),
+    }
+}
+
+/// Who is making a request, resolved by `authorize`
+#[derive(Clone, Copy)]
+pub enum Access {
+    /// No token on a baseline api while tokens are optional.
+    /// Allowed everything like before there were accounts, except the views only meant for admins.
+    Unrestricted,
+    /// No token while tokens are required, or on an api added since
+    Anonymous,
+    User {
+        id: u32,
+        role: Role,
+    },
+}
+
+impl Access {
+    /// An admin who sent a token, as needed by everything only meant for admins
+    pub fn is_signed_admin(&self) -> bool {
+        matches!(
+            self,
+            Access::User {
+                role: Role::Admin,
+                ..
+            }
+        )
+    }
+
+    /// Contestants only read their own source code
+    pub fn can_read_source(&self, owner: u32) -> bool {
+        match self {
+            Access::Unrestricted => true,
+            Access::Anonymous => false,
+            Access::User { id, role } => *role != Role::Contestant || *id == owner,
+        }
+    }
+
+    /// The user itself or an admin who sent a token, who read what is private to the user,
+    /// like results a frozen ranklist hides and the email
+    pub fn is_owner_or_admin(&self, owner: u32) -> bool {
+        match self {
+            Access::Unrestricted | Access::Anonymous => false,
+            Access::User { id, role } => *role == Role::Admin || *id == owner,
+        }
+    }
+
+    /// Non-sample test data is for problem setters
+    pub fn can_read_hidden_data(&self) -> bool {
+        match self {
+            Access::Unrestricted | Access::Anonymous => false,
+            Access::User { role, .. } => *role == Role::Admin || *role == Role::Setter,
+        }
+    }
+}
+
+/// The access of a request checked by `authorize`
+pub fn access(req: &HttpRequest) -> Access {
+    req.extensions()
+        .get::<Access>()
+        .copied()
+        .unwrap_or(Access::Anonymous)
+}
+
+/// Who may call an api
+#[derive(PartialEq, PartialOrd)]
+enum Need {
+    Anyone,
+    SignedIn,
+    /// Anyone but observers
+    Participant,
+    Admin,
+}
+
+fn need(method: &Method, pattern: Option<&str>) -> Need {
+    let pattern = match pattern {
+        Some(pattern) => pattern,
+        // Unknown paths end in 404 anyway
+        None => return Need::Anyone,
+    };
+    match (method.as_str(), pattern) {
+        ("POST", "/login") | ("POST", "/users") | ("GET", "/hello") | ("GET", "/hello/{name}") => {
+            Need::Anyone
+        }
+        ("POST", "/jobs")
+        | ("POST", "/runs")
+        | ("POST", "/teams")
+        | ("POST", "/contests/{contest_id}/register")
+        | ("POST", "/contests/{contest_id}/clarifications")
+        | ("POST", "/contests/{contest_id}/virtual") => Need::Participant,
+        ("POST", "/contests")
+        | ("PUT", "/jobs/{jobid}")
+        | ("POST", "/jobs/rejudge")
+        | ("GET", "/jobs/rejudge/{rejudge_id}")
+        | ("POST", "/contests/{contest_id}/unfreeze")
+        | ("POST", "/contests/{contest_id}/extend")
+        | ("POST", "/contests/{contest_id}/pause")
+        | ("POST", "/contests/{contest_id}/resume")
+        | ("POST", "/contests/{contest_id}/end")
+        | ("PUT", "/contests/{contest_id}/participants/{id}")
+        | ("DELETE", "/contests/{contest_id}/participants/{id}")
+        | ("PUT", "/contests/{contest_id}/clarifications/{id}")
+        | ("POST", "/users/import")
+        | ("DELETE", "/users/{id}")
+        | ("POST", "/internal/exit") => Need::Admin,
+        _ => Need::SignedIn,
+    }
+}
+
+/// The apis of the judge from before there were accounts.
+/// While tokens are optional they keep working without one, so baseline clients
+/// can still submit as any user, create contests, rejudge and shut the judge down.
+fn baseline(method: &Method, pattern: Option<&str>) -> bool {
+    matches!(
+        (method.as_str(), pattern),
+        ("POST", Some("/jobs"))
+            | ("GET", Some("/jobs"))
+            | ("GET", Some("/jobs/{jobid}"))
+            | ("PUT", Some("/jobs/{jobid}"))
+            | ("POST", Some("/users"))
+            | ("GET", Some("/users"))
+            | ("POST", Some("/contests"))
+            | ("GET", Some("/contests"))
+            | ("GET", Some("/contests/{contest_id}"))
+            | ("GET", Some("/contests/{contest_id}/ranklist"))
+            | ("GET", Some("/hello/{name}"))
+            | ("POST", Some("/internal/exit"))
+    )
+}
+
+fn check(req: &HttpRequest) -> ApiResult<Access> {
+    let access = match token_user(req)? {
+        Some(id) => {
+            let lock = USER_LIST.lock().unwrap();
+            match lock.iter().find(|x| x.id == Some(id)) {
+                Some(user) if user.disabled => {
+                    return Err(Error::Forbidden(format!("User {} is disabled.", id)))
+                }
+                Some(user) => Access::User {
+                    id,
+                    role: user.role,
+                },
+                None => return Err(Error::Unauthorized(format!("User {} not found.", id))),
+            }
+        }
+        None => match req.app_data::<web::Data<Config>>() {
+            Some(config)
+                if !config.auth.required
+                    && baseline(req.method(), req.match_pattern().as_deref()) =>
+            {
+                return Ok(Access::Unrestricted)
+            }
+            _ => Access::Anonymous,
+        },
+    };
+
+    let need = need(req.method(), req.match_pattern().as_deref());
+    let have = match access {
+        Access::Unrestricted => Need::Admin,
+        Access::Anonymous => Need::Anyone,
+        Access::User { role, .. } => match role {
+            Role::Admin => Need::Admin,
+            Role::Setter | Role::Contestant => Need::Participant,
+            Role::Observer => Need::SignedIn,
+        },
+    };
+    if have >= need {
+        Ok(access)
+    } else if have == Need::Anyone {
+        Err(Error::Unauthorized("Missing token.".to_string()))
+    } else {
+        Err(Error::Forbidden("Not allowed for this role.".to_string()))
+    }
+}
+
+/// Middleware resolving the token of every request into an `Access`,
+/// and rejecting requests the role isn't allowed to make
+pub fn authorize<S, B>(
+    mut req: ServiceRequest,
+    srv: &S,
+) -> Either<Ready<Result<ServiceResponse<B>, actix_web::Error>>, S::Future>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    match check(req.parts_mut().0) {
+        Ok(access) => {
+            req.extensions_mut().insert(access);
+            Either::Right(srv.call(req))
+        }
+        Err(err) => Either::Left(ready(Err(err.into()))),
+    }
+}
+
+/// The user acting in this request.
+/// With a token it is the token's user, and `claimed` (the user named in the body)
+/// must match unless the user is an admin.
+/// Without one, `claimed` is only trusted on baseline apis while tokens are optional.
+pub fn acting_user(req: &HttpRequest, claimed: Option<u32>) -> ApiResult<u32> {
+    match access(req) {
+        Access::User { id, role } => match claimed {
+            Some(claimed) if claimed != id && role != Role::Admin => Err(Error::Forbidden(
+                format!("Token doesn't belong to user {}.", claimed),
+            )),
+            Some(claimed) => Ok(claimed),
+            None => Ok(id),
+        },
+        Access::Anonymous => Err(Error::Unauthorized("Missing token.".to_string())),
+        Access::Unrestricted => match claimed {
+            Some(claimed) => Ok(claimed),
+            None => Err(Error::InvalidArgument("Missing user_id.".to_string())),
+        },
+    }
+}
@@ -0,0 +1,141 @@
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{self, Language};
+
+const CACHE_DIR: &str = "./CACHEDIR";
+
+/// A compile result kept in the cache.
+/// The executable is stored as `CACHE_DIR/<key>` when compilation succeeded.
+struct Entry {
+    success: bool,
+    compile_time: u128,
+    /// Messages of the compiler when compilation failed
+    log: String,
+    size: u64,
+    last_used: u64,
+}
+
+struct BinaryCache {
+    entries: HashMap<String, Entry>,
+    total_size: u64,
+    tick: u64,
+}
+
+/// Numbers temporary copies, so concurrent inserts of the same key don't share one
+static TEMP_ID: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref BINARY_CACHE: Arc<Mutex<BinaryCache>> = {
+        // Executables of the last run are not indexed, so start from an empty directory
+        fs::remove_dir_all(CACHE_DIR).ok();
+        fs::create_dir_all(CACHE_DIR).unwrap();
+        Arc::new(Mutex::new(BinaryCache {
+            entries: HashMap::new(),
+            total_size: 0,
+            tick: 0,
+        }))
+    };
+}
+
+/// Hash the language config together with the source code,
+/// so changing the compiler command invalidates old executables
+pub fn key(language: &Language, source_code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(language.name.as_bytes());
+    for i in language.command.iter() {
+        hasher.update([0]);
+        hasher.update(i.as_bytes());
+    }
+    hasher.update([0]);
+    hasher.update(source_code.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Look up a compile result, copying the cached executable to `output` on success,
+/// or writing the compiler messages to `log` on failure.
+/// return whether success and compile time
+pub fn lookup(key: &str, output: &str, log: &str) -> Option<(bool, u128)> {
+    let mut lock = BINARY_CACHE.lock().unwrap();
+    lock.tick += 1;
+    let tick = lock.tick;
+    let entry = lock.entries.get_mut(key)?;
+    let restored = if entry.success {
+        fs::copy(format!("{}/{}", CACHE_DIR, key), output).map(|_| ())
+    } else {
+        fs::write(log, &entry.log)
+    };
+    if restored.is_err() {
+        return None;
+    }
+    entry.last_used = tick;
+    Some((entry.success, entry.compile_time))
+}
+
+/// Store a compile result with the executable at `output` or the compiler messages at `log`,
+/// evicting the least recently used entries until the cache fits in the configured limits
+pub fn insert(
+    key: &str,
+    output: &str,
+    log: &str,
+    (success, compile_time): (bool, u128),
+    limit: &config::Cache,
+) {
+    // Copy to a temporary file outside the lock and rename it into place under it,
+    // so lookups never copy a partly written executable
+    let path = format!("{}/{}", CACHE_DIR, key);
+    let temp = format!("{}.{}.tmp", path, TEMP_ID.fetch_add(1, Ordering::SeqCst));
+    let (size, messages) = if success {
+        match fs::copy(output, &temp) {
+            Ok(bytes) => (bytes, String::new()),
+            Err(_) => {
+                fs::remove_file(&temp).ok();
+                return;
+            }
+        }
+    } else {
+        match fs::read_to_string(log) {
+            Ok(text) => (text.len() as u64, text),
+            Err(_) => return,
+        }
+    };
+
+    let mut lock = BINARY_CACHE.lock().unwrap();
+    if success && fs::rename(&temp, &path).is_err() {
+        fs::remove_file(&temp).ok();
+        return;
+    }
+    lock.tick += 1;
+    let last_used = lock.tick;
+    if let Some(old) = lock.entries.insert(
+        key.to_string(),
+        Entry {
+            success,
+            compile_time,
+            log: messages,
+            size,
+            last_used,
+        },
+    ) {
+        lock.total_size -= old.size;
+    }
+    lock.total_size += size;
+
+    while lock.total_size > limit.max_size || lock.entries.len() > limit.max_entries {
+        let oldest = lock
+            .entries
+            .iter()
+            .min_by_key(|x| x.1.last_used)
+            .map(|x| x.0.clone())
+            .unwrap();
+        let entry = lock.entries.remove(&oldest).unwrap();
+        lock.total_size -= entry.size;
+        if entry.success {
+            fs::remove_file(format!("{}/{}", CACHE_DIR, oldest)).ok();
+        }
+    }
+}
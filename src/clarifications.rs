@@ -0,0 +1,269 @@
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::auth::{self, Access};
+use crate::contests::{Contest, CONTEST_LIST};
+use crate::error::{ApiResult, Error};
+use crate::events::{self, Topic};
+
+/// A question of a participant with its answer, or an announcement of the admins
+#[derive(Serialize, Clone)]
+pub struct Clarification {
+    pub id: u32,
+    pub contest_id: u32,
+    /// Who asked, absent for announcements
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u32>,
+    /// Team of the asking user in team contests, whose members all read it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<u32>,
+    /// The problem it is about, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub question: Option<String>,
+    /// The text of announcements
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    /// Read by every participant, otherwise only by whoever asked
+    pub public: bool,
+    pub created_time: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answered_time: Option<String>,
+}
+
+/// Who reads clarifications
+#[derive(Clone, Copy)]
+pub enum Reader {
+    Admin,
+    User {
+        id: u32,
+        team_id: Option<u32>,
+    },
+    /// Public clarifications only
+    Anonymous,
+}
+
+impl Clarification {
+    pub fn readable_by(&self, reader: Reader) -> bool {
+        match reader {
+            Reader::Admin => true,
+            Reader::User { id, team_id } => {
+                self.public
+                    || self.user_id == Some(id)
+                    || (team_id.is_some() && self.team_id == team_id)
+            }
+            Reader::Anonymous => self.public,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PostClarification {
+    /// Taken from the token when logged in
+    user_id: Option<u32>,
+    problem_id: Option<u32>,
+    /// Asked by a participant
+    question: Option<String>,
+    /// Sent alone by admins to make an announcement
+    answer: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Answer {
+    answer: String,
+    /// Show the question and answer to every participant
+    #[serde(default)]
+    public: bool,
+}
+
+lazy_static! {
+    static ref CLARIFICATION_LIST: Arc<Mutex<Vec<Clarification>>> =
+        Arc::new(Mutex::new(Vec::new()));
+}
+
+fn find_contest(contest_id: u32) -> ApiResult<Contest> {
+    match CONTEST_LIST.lock().unwrap().get(contest_id as usize) {
+        Some(contest) if contest.id != 0 => Ok(contest.clone()),
+        _ => Err(Error::NotFound(format!(
+            "Contest {} not found.",
+            contest_id
+        ))),
+    }
+}
+
+/// The reader of a request, with its team in team contests
+fn reader(req: &HttpRequest, contest: &Contest) -> Reader {
+    match auth::access(req) {
+        access if access.is_signed_admin() => Reader::Admin,
+        Access::User { id, .. } => Reader::User {
+            id,
+            team_id: if contest.teams {
+                crate::teams::team_of(&contest.team_ids, id)
+            } else {
+                None
+            },
+        },
+        _ => Reader::Anonymous,
+    }
+}
+
+/// Ask a question about a contest, or one of its problems.
+/// Admins make an announcement by sending only an answer.
+#[post("/contests/{contest_id}/clarifications")]
+async fn post_clarification(
+    contest_id: web::Path<u32>,
+    body: web::Json<PostClarification>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let contest = find_contest(*contest_id)?;
+    let admin = auth::access(&req).is_signed_admin();
+    if let Some(problem_id) = body.problem_id {
+        if !contest.problem_ids.contains(&(problem_id as usize)) {
+            return Err(Error::InvalidArgument(
+                "Problem doesn't exist in this contest.".to_string(),
+            ));
+        }
+    }
+
+    let mut clarification = Clarification {
+        id: 0,
+        contest_id: *contest_id,
+        user_id: None,
+        team_id: None,
+        problem_id: body.problem_id,
+        question: None,
+        answer: None,
+        public: false,
+        created_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        answered_time: None,
+    };
+    match (body.question.as_ref(), body.answer.as_ref()) {
+        (Some(question), None) if !question.trim().is_empty() => {
+            let user_id = auth::acting_user(&req, body.user_id)?;
+            crate::users::check_active(user_id)?;
+            let team_id = if contest.teams {
+                crate::teams::team_of(&contest.team_ids, user_id)
+            } else {
+                None
+            };
+            let registered = if contest.teams {
+                team_id.is_some()
+            } else {
+                contest.user_ids.contains(&(user_id as usize))
+            };
+            if !registered && !admin {
+                return Err(Error::Forbidden(format!(
+                    "User {} is not in contest {}.",
+                    user_id, contest_id
+                )));
+            }
+            clarification.user_id = Some(user_id);
+            clarification.team_id = team_id;
+            clarification.question = Some(question.clone());
+        }
+        (None, Some(answer)) if !answer.trim().is_empty() => {
+            if !admin {
+                return Err(Error::Forbidden(
+                    "Only admins make announcements.".to_string(),
+                ));
+            }
+            clarification.answer = Some(answer.clone());
+            clarification.public = true;
+            clarification.answered_time = Some(clarification.created_time.clone());
+        }
+        _ => {
+            return Err(Error::InvalidArgument(
+                "Send either a question or an announcement.".to_string(),
+            ))
+        }
+    }
+
+    let mut lock = CLARIFICATION_LIST.lock().unwrap();
+    clarification.id = lock.len() as u32;
+    lock.push(clarification.clone());
+    events::publish_clarification(&clarification);
+    drop(lock);
+    Ok(HttpResponse::Ok().json(clarification))
+}
+
+/// Answer a question, privately or to every participant.
+/// Answering again replaces the answer.
+#[put("/contests/{contest_id}/clarifications/{id}")]
+async fn answer_clarification(
+    path: web::Path<(u32, u32)>,
+    body: web::Json<Answer>,
+) -> ApiResult<HttpResponse> {
+    let (contest_id, id) = path.into_inner();
+    if body.answer.trim().is_empty() {
+        return Err(Error::InvalidArgument("Missing answer.".to_string()));
+    }
+    let mut lock = CLARIFICATION_LIST.lock().unwrap();
+    let clarification = match lock.get_mut(id as usize) {
+        Some(x) if x.contest_id == contest_id => x,
+        _ => return Err(Error::NotFound(format!("Clarification {} not found.", id))),
+    };
+    if clarification.question.is_none() {
+        return Err(Error::InvalidState(format!(
+            "Clarification {} is an announcement.",
+            id
+        )));
+    }
+    clarification.answer = Some(body.answer.clone());
+    clarification.public = body.public;
+    clarification.answered_time = Some(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true));
+    let res = clarification.clone();
+    events::publish_clarification(&res);
+    drop(lock);
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Clarifications of a contest the requester may read, oldest first
+#[get("/contests/{contest_id}/clarifications")]
+async fn get_clarifications(
+    contest_id: web::Path<u32>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let contest = find_contest(*contest_id)?;
+    let reader = reader(&req, &contest);
+    let lock = CLARIFICATION_LIST.lock().unwrap();
+    let res: Vec<Clarification> = lock
+        .iter()
+        .filter(|x| x.contest_id == *contest_id && x.readable_by(reader))
+        .cloned()
+        .collect();
+    drop(lock);
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Stream new and answered clarifications the requester may read as server-sent events
+#[get("/contests/{contest_id}/clarifications/events")]
+async fn get_clarification_events(
+    contest_id: web::Path<u32>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let contest = find_contest(*contest_id)?;
+    let topic = Topic::Clarifications(*contest_id, reader(&req, &contest));
+    Ok(events::response(events::subscribe(topic, None, false)))
+}
+
+#[get("/contests/{contest_id}/clarifications/{id}")]
+async fn get_clarification(
+    path: web::Path<(u32, u32)>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let (contest_id, id) = path.into_inner();
+    let contest = find_contest(contest_id)?;
+    let reader = reader(&req, &contest);
+    let lock = CLARIFICATION_LIST.lock().unwrap();
+    match lock.get(id as usize) {
+        // Unreadable ones are not found, so nothing is told about them
+        Some(x) if x.contest_id == contest_id && x.readable_by(reader) => {
+            Ok(HttpResponse::Ok().json(x))
+        }
+        _ => Err(Error::NotFound(format!("Clarification {} not found.", id))),
+    }
+}
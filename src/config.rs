@@ -1,4 +1,5 @@
-use std::fs::File;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
 use std::io::BufReader;
 
 use serde::Deserialize;
@@ -9,6 +10,12 @@ pub struct Config {
     server: Server,
     pub problems: Vec<Problem>,
     pub languages: Vec<Language>,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub auth: Auth,
 }
 
 impl Config {
@@ -16,7 +23,7 @@ impl Config {
         let file = File::open(path).unwrap();
         let reader = BufReader::new(file);
         let config: Config = serde_json::from_reader(reader).unwrap();
-        return config;
+        config
     }
 }
 
@@ -26,13 +33,129 @@ struct Server {
     bind_port: Option<u32>,
 }
 
+/// Limits of the compiled binary cache
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Cache {
+    /// Total size of cached executables in bytes
+    pub max_size: u64,
+    pub max_entries: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            max_size: 256 * 1024 * 1024,
+            max_entries: 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Auth {
+    /// Require a bearer token for every api but logging in and signing up.
+    ///
+    /// Off by default, for clients from before there were accounts. Then the
+    /// baseline apis (jobs, users, contests, ranklists and `/internal/exit`) let
+    /// requests without a token do ANYTHING: roles are not enforced and
+    /// `user_id` in the body is trusted, so anyone can submit as any user,
+    /// create and change contests, rejudge and shut the judge down.
+    /// Every other api needs a token either way. Turn this on for a judge
+    /// reachable by contestants.
+    pub required: bool,
+    /// Seconds a login token stays valid
+    pub token_lifetime: i64,
+    /// Lets the root user, an admin, log in
+    pub root_password: Option<String>,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Auth {
+            required: false,
+            token_lifetime: 24 * 60 * 60,
+            root_password: None,
+        }
+    }
+}
+
+/// An url notified of jobs and contests
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent in `X-Signature`, unsigned when absent
+    pub secret: Option<String>,
+    /// Any of job_finished, contest_started and contest_ended, all of them when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Retries after a failed delivery, waiting `backoff` ms and doubling it every time,
+    /// up to an hour
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_backoff() -> u64 {
+    1000
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Problem {
     pub id: u32,
-    name: String,
+    pub name: String,
     pub r#type: String,
     pub misc: Misc,
     pub cases: Vec<Case>,
+    /// Names of the languages jobs may use, any language when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_languages: Option<Vec<String>>,
+}
+
+impl Problem {
+    /// Hash the problem config together with the size and modified time of its data files,
+    /// so fixing the test data changes the version
+    pub fn version(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(self).unwrap());
+        for i in self.cases.iter() {
+            for path in [&i.input_file, &i.answer_file] {
+                if let Ok(metadata) = fs::metadata(path) {
+                    hasher.update(metadata.len().to_le_bytes());
+                    if let Ok(modified) = metadata.modified() {
+                        hasher.update(format!("{:?}", modified).as_bytes());
+                    }
+                }
+            }
+        }
+        format!("{:x}", hasher.finalize())[..16].to_string()
+    }
+
+    pub fn allows(&self, language: &str) -> bool {
+        allows(&self.allowed_languages, language)
+    }
+
+    /// Score of a job passing every case, samples aren't scored
+    pub fn full_score(&self) -> f32 {
+        self.cases
+            .iter()
+            .filter(|x| !x.sample)
+            .map(|x| x.score)
+            .sum()
+    }
+}
+
+/// Whether `language` is in the allowed languages, if there are any
+pub fn allows(allowed_languages: &Option<Vec<String>>, language: &str) -> bool {
+    match allowed_languages {
+        Some(names) => names.iter().any(|x| x == language),
+        None => true,
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -41,7 +164,10 @@ pub struct Case {
     pub input_file: String,
     pub answer_file: String,
     pub time_limit: u64,
-    memory_limit: u32,
+    pub memory_limit: u32,
+    /// Sample cases are not scored, and their outputs are shown in the job
+    #[serde(default)]
+    pub sample: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -1,24 +1,38 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use chrono::Duration;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::{cmp::Ordering, vec};
 
-use crate::jobs::{Result, RESPONSE_LIST};
-use crate::users::USER_LIST;
-use crate::{config::Config, users::User};
+use crate::auth;
+use crate::config::Config;
+use crate::error::{ApiResult, Error};
+use crate::events::{self, Topic};
+use crate::jobs::{Participation, Response, Result, RESPONSE_LIST};
+use crate::teams::{Team, TEAM_LIST};
+use crate::users::{PublicUser, User, USER_LIST};
+use crate::virtuals::{self, VirtualRun};
 
 #[derive(Deserialize)]
 struct Info {
     scoring_rule: Option<String>,
     tie_breaker: Option<String>,
+    /// Show results after the freeze too, for admins
+    #[serde(default)]
+    live: bool,
+    /// Rank virtual runs among the participants, timing their jobs from the start of the run
+    #[serde(default, rename = "virtual")]
+    with_virtual: bool,
 }
 
-#[derive(Deserialize, Serialize)]
-struct Error {
-    reason: String,
-    code: u8,
-    message: String,
+#[derive(Deserialize)]
+struct Unfreeze {
+    /// Reveal one problem of one participant instead of everything
+    #[serde(default)]
+    step: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -27,9 +41,257 @@ pub struct Contest {
     pub name: String,
     pub from: String,
     pub to: String,
+    /// Config ids of `problems`, in the same order
     pub problem_ids: Vec<usize>,
     pub user_ids: Vec<usize>,
     pub submission_limit: u32,
+    #[serde(default)]
+    pub problems: Vec<ContestProblem>,
+    /// Names of the languages jobs may use, any language when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_languages: Option<Vec<String>>,
+    /// Teams take part instead of users, and submissions count for the team of the user
+    #[serde(default)]
+    pub teams: bool,
+    #[serde(default)]
+    pub team_ids: Vec<usize>,
+    #[serde(default)]
+    pub registration: Registration,
+    /// Asked for by `invite_code` contests, never shown
+    #[serde(default, skip_serializing)]
+    pub invite_code: Option<String>,
+    /// Registration is open from this time, or from the beginning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_from: Option<String>,
+    /// Registration is open until this time, or until the contest ends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_to: Option<String>,
+    /// Users, or teams in team contests, waiting for approval.
+    /// Only shown to admins, through `ContestView`.
+    #[serde(default, skip_serializing)]
+    pub pending_ids: Vec<usize>,
+    /// Minutes added for each rejected job before an accepted one in icpc scoring
+    #[serde(default = "default_penalty_minutes")]
+    pub penalty_minutes: u32,
+    /// Whether compilation errors count as rejected in icpc scoring
+    #[serde(default)]
+    pub penalize_compilation_error: bool,
+    /// Accept jobs after the end, which count in no contest ranklist
+    #[serde(default)]
+    pub upsolving: bool,
+    /// Results of jobs created from this time are hidden from the public ranklist
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub freeze_time: Option<String>,
+    /// Hidden jobs revealed one step at a time.
+    /// Only shown to admins, through `ContestView`.
+    #[serde(default, skip_serializing)]
+    pub revealed_ids: Vec<u32>,
+    /// Every hidden job is revealed
+    #[serde(default)]
+    pub unfrozen: bool,
+    /// When an admin paused the contest, which stops submissions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused_at: Option<String>,
+}
+
+/// A problem as it appears in a contest
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ContestProblem {
+    /// Id of the problem in the config
+    pub id: usize,
+    /// Like A, B, C, following the order of the problems when not given
+    #[serde(default)]
+    pub label: String,
+    /// Shown instead of the name in the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Ranklist scores are scaled so that a full score is worth this much
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_score: Option<f32>,
+    /// Ranklist scores are multiplied by this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f32>,
+    /// Overrides `submission_limit` of the contest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submission_limit: Option<u32>,
+}
+
+impl ContestProblem {
+    fn new(id: usize) -> Self {
+        ContestProblem {
+            id,
+            label: String::new(),
+            name: None,
+            max_score: None,
+            weight: None,
+            submission_limit: None,
+        }
+    }
+
+    /// Factor from job scores to ranklist scores
+    fn scale(&self, config: &Config) -> f32 {
+        if let Some(weight) = self.weight {
+            return weight;
+        }
+        let full_score = config
+            .problems
+            .iter()
+            .find(|x| x.id as usize == self.id)
+            .map(|x| x.full_score())
+            .unwrap_or(0.0);
+        match self.max_score {
+            Some(max_score) if full_score > 0.0 => max_score / full_score,
+            _ => 1.0,
+        }
+    }
+}
+
+/// A, B, ..., Z, AA, AB, ... for the problem at `index`
+fn default_label(index: usize) -> String {
+    let mut label = Vec::new();
+    let mut i = index + 1;
+    while i > 0 {
+        i -= 1;
+        label.push(b'A' + (i % 26) as u8);
+        i /= 26;
+    }
+    label.reverse();
+    String::from_utf8(label).unwrap()
+}
+
+/// Where a contest is in its lifecycle, derived from its times
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Upcoming,
+    Running,
+    /// Running, with the ranklist frozen
+    Frozen,
+    Paused,
+    Ended,
+}
+
+/// A contest with its state, as sent in responses
+#[derive(Serialize)]
+pub struct ContestView<'a> {
+    #[serde(flatten)]
+    contest: &'a Contest,
+    state: State,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_ids: Option<&'a [usize]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revealed_ids: Option<&'a [u32]>,
+}
+
+impl Contest {
+    pub fn start(&self) -> DateTime<Utc> {
+        parse_time(&self.from)
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        parse_time(&self.to)
+    }
+
+    pub fn state(&self, now: DateTime<Utc>) -> State {
+        // Contest 0 holds every job and is always open
+        if self.id == 0 {
+            return State::Running;
+        }
+        if now < self.start() {
+            State::Upcoming
+        } else if self.paused_at.is_some() {
+            State::Paused
+        } else if now > self.end() {
+            State::Ended
+        } else if !self.unfrozen
+            && matches!(self.freeze_time.as_ref(), Some(x) if now >= parse_time(x))
+        {
+            State::Frozen
+        } else {
+            State::Running
+        }
+    }
+
+    /// The submission limit of a problem, which may override that of the contest
+    pub fn limit_of(&self, problem_id: u32) -> u32 {
+        self.problems
+            .iter()
+            .find(|x| x.id == problem_id as usize)
+            .and_then(|x| x.submission_limit)
+            .unwrap_or(self.submission_limit)
+    }
+
+    /// Time from the start of the contest to the creation of the job,
+    /// or from the start of the run for virtual jobs
+    fn elapsed(&self, job: &Response, runs: &[VirtualRun]) -> Duration {
+        let from = match job.submission.participation {
+            Participation::Virtual => runs
+                .iter()
+                .find(|x| x.user_id == job.submission.user_id)
+                .map(|x| x.start())
+                .unwrap_or_else(|| self.start()),
+            _ => self.start(),
+        };
+        parse_time(&job.created_time) - from
+    }
+
+    pub fn allows(&self, language: &str) -> bool {
+        crate::config::allows(&self.allowed_languages, language)
+    }
+
+    /// The contest as sent to a reader, with the waiting list
+    /// and the revealed jobs only for admins
+    pub fn view(&self, admin: bool) -> ContestView<'_> {
+        ContestView {
+            contest: self,
+            state: self.state(Utc::now()),
+            pending_ids: admin.then(|| &self.pending_ids[..]),
+            revealed_ids: admin.then(|| &self.revealed_ids[..]),
+        }
+    }
+
+    /// Whether the public ranklist hides the result of the job
+    pub fn hides(&self, job: &Response) -> bool {
+        if job.submission.participation != Participation::Official {
+            return false;
+        }
+        match self.freeze_time.as_ref() {
+            Some(freeze_time) => {
+                !self.unfrozen
+                    && parse_time(&job.created_time) >= parse_time(freeze_time)
+                    && !self.revealed_ids.contains(&job.id)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Whether the public ranklist of the contest of the job hides its result.
+/// Locks `CONTEST_LIST`, which may be done while holding `RESPONSE_LIST`.
+pub fn hidden(job: &Response) -> bool {
+    job.submission.contest_id != 0
+        && matches!(
+            CONTEST_LIST.lock().unwrap().get(job.submission.contest_id as usize),
+            Some(contest) if contest.hides(job)
+        )
+}
+
+fn default_penalty_minutes() -> u32 {
+    20
+}
+
+/// How users join a contest by themselves
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Registration {
+    Open,
+    /// Open to those who know `invite_code`
+    InviteCode,
+    /// Registering waits for an admin to approve
+    Approval,
+    /// Only admins add participants, like contests were before registration
+    #[default]
+    Closed,
 }
 
 #[derive(Deserialize, Clone)]
@@ -38,9 +300,41 @@ struct PostContest {
     name: String,
     from: String,
     to: String,
+    /// Sorted, and labeled in that order. Not needed when giving `problems`.
+    #[serde(default)]
     problem_ids: Vec<usize>,
+    #[serde(default)]
     user_ids: Vec<usize>,
     submission_limit: u32,
+    /// Problems in the order of the contest, with their labels and overrides
+    #[serde(default)]
+    problems: Vec<ContestProblem>,
+    allowed_languages: Option<Vec<String>>,
+    #[serde(default)]
+    teams: bool,
+    #[serde(default)]
+    team_ids: Vec<usize>,
+    #[serde(default)]
+    registration: Registration,
+    invite_code: Option<String>,
+    registration_from: Option<String>,
+    registration_to: Option<String>,
+    penalty_minutes: Option<u32>,
+    #[serde(default)]
+    penalize_compilation_error: bool,
+    #[serde(default)]
+    upsolving: bool,
+    freeze_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Register {
+    /// Taken from the token when logged in
+    user_id: Option<u32>,
+    /// The team to register in team contests
+    team_id: Option<u32>,
+    /// For `invite_code` contests
+    code: Option<String>,
 }
 
 lazy_static! {
@@ -52,127 +346,872 @@ lazy_static! {
         problem_ids: Vec::new(),
         user_ids: Vec::new(),
         submission_limit: 0,
+        problems: Vec::new(),
+        allowed_languages: None,
+        teams: false,
+        team_ids: Vec::new(),
+        registration: Registration::Closed,
+        invite_code: None,
+        registration_from: None,
+        registration_to: None,
+        pending_ids: Vec::new(),
+        penalty_minutes: default_penalty_minutes(),
+        penalize_compilation_error: false,
+        upsolving: false,
+        freeze_time: None,
+        revealed_ids: Vec::new(),
+        unfrozen: false,
+        paused_at: None,
     }]));
 }
 
 #[post("/contests")]
-async fn post_contest(body: web::Json<PostContest>, config: web::Data<Config>) -> impl Responder {
+async fn post_contest(
+    body: web::Json<PostContest>,
+    config: web::Data<Config>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
     let mut body = body.clone();
     body.problem_ids.sort();
+    if body.problems.is_empty() {
+        body.problems = body
+            .problem_ids
+            .iter()
+            .map(|x| ContestProblem::new(*x))
+            .collect();
+    }
+    for (i, problem) in body.problems.iter_mut().enumerate() {
+        if problem.label.is_empty() {
+            problem.label = default_label(i);
+        }
+    }
     body.user_ids.sort();
+    body.team_ids.sort();
+    body.team_ids.dedup();
+
+    let times = validate(&body, &config)?;
+    check_teams(&body.team_ids, &TEAM_LIST.lock().unwrap())?;
 
     let mut contest_list = CONTEST_LIST.lock().unwrap();
+    let mut contest = Contest {
+        id: body.id.unwrap_or(contest_list.len()),
+        name: body.name.clone(),
+        from: times.from,
+        to: times.to,
+        problem_ids: body.problems.iter().map(|x| x.id).collect(),
+        user_ids: body.user_ids.clone(),
+        submission_limit: body.submission_limit,
+        problems: body.problems.clone(),
+        allowed_languages: body.allowed_languages.clone(),
+        teams: body.teams,
+        team_ids: body.team_ids.clone(),
+        registration: body.registration,
+        invite_code: body.invite_code.clone(),
+        registration_from: times.registration_from,
+        registration_to: times.registration_to,
+        pending_ids: Vec::new(),
+        penalty_minutes: body.penalty_minutes.unwrap_or_else(default_penalty_minutes),
+        penalize_compilation_error: body.penalize_compilation_error,
+        upsolving: body.upsolving,
+        freeze_time: times.freeze_time,
+        revealed_ids: Vec::new(),
+        unfrozen: false,
+        paused_at: None,
+    };
+    if let Some(id) = body.id {
+        if id >= contest_list.len() {
+            drop(contest_list);
+            return Err(Error::NotFound(format!("Contest {} not found", id)));
+        }
+        // Requests still waiting stay unless the update admitted them
+        let pending: Vec<usize> = contest_list[id]
+            .pending_ids
+            .iter()
+            .filter(|x| !participants(&contest).contains(x))
+            .copied()
+            .collect();
+        contest.pending_ids = pending;
+        contest.revealed_ids = contest_list[id].revealed_ids.clone();
+        contest.unfrozen = contest_list[id].unfrozen;
+        contest.paused_at = contest_list[id].paused_at.clone();
+        contest_list[id] = contest.clone();
+    } else {
+        contest_list.push(contest.clone());
+    }
+    drop(contest_list);
+    Ok(HttpResponse::Ok().json(contest.view(auth::access(&req).is_signed_admin())))
+}
 
-    if *body.problem_ids.iter().max().unwrap() >= config.problems.len() {
-        drop(contest_list);
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: "Problem id not found".to_string(),
-        });
+/// Times of a posted contest, normalized like job times
+struct Times {
+    from: String,
+    to: String,
+    registration_from: Option<String>,
+    registration_to: Option<String>,
+    freeze_time: Option<String>,
+}
+
+/// Check every field of a posted contest, naming the first wrong one
+fn validate(body: &PostContest, config: &Config) -> ApiResult<Times> {
+    fn invalid_argument(field: &str, value: impl std::fmt::Display) -> Error {
+        Error::InvalidArgument(format!("Invalid {} '{}'.", field, value))
     }
-    if body.id.is_some() && body.id.unwrap() == 0 {
-        drop(contest_list);
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: "Contest id should not be 0.".to_string(),
-        });
+    fn time(field: &str, value: &str) -> ApiResult<DateTime<Utc>> {
+        match DateTime::parse_from_rfc3339(value) {
+            Ok(x) => Ok(x.with_timezone(&Utc)),
+            Err(_) => Err(invalid_argument(field, value)),
+        }
     }
-    let user_list = USER_LIST.lock().unwrap();
-    if *body.user_ids.iter().max().unwrap() >= user_list.len() {
-        drop(user_list);
-        drop(contest_list);
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: "User id not found".to_string(),
-        });
+    fn optional_time(field: &str, value: &Option<String>) -> ApiResult<Option<DateTime<Utc>>> {
+        match value {
+            Some(value) => time(field, value).map(Some),
+            None => Ok(None),
+        }
+    }
+    fn no_repeats(field: &str, sorted: &[usize]) -> ApiResult<()> {
+        match sorted.windows(2).find(|x| x[0] == x[1]) {
+            Some(x) => Err(Error::InvalidArgument(format!(
+                "{} {} is repeated in {}.",
+                field, x[0], field
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    if body.id == Some(0) {
+        return Err(Error::NotFound("Contest id should not be 0.".to_string()));
+    }
+    if body.name.trim().is_empty() {
+        return Err(Error::InvalidArgument("Missing name.".to_string()));
+    }
+    let from = time("from", &body.from)?;
+    let to = time("to", &body.to)?;
+    if to <= from {
+        return Err(Error::InvalidArgument(
+            "Contest must end after it starts.".to_string(),
+        ));
+    }
+
+    if body.problems.is_empty() {
+        return Err(Error::InvalidArgument("Missing problem_ids.".to_string()));
     }
+    let mut problem_ids: Vec<usize> = body.problems.iter().map(|x| x.id).collect();
+    problem_ids.sort_unstable();
+    if !body.problem_ids.is_empty() && body.problem_ids != problem_ids {
+        return Err(Error::InvalidArgument(
+            "problem_ids don't match problems.".to_string(),
+        ));
+    }
+    no_repeats("problem_ids", &problem_ids)?;
+    if problem_ids
+        .iter()
+        .any(|x| !config.problems.iter().any(|y| y.id as usize == *x))
+    {
+        return Err(Error::NotFound("Problem id not found".to_string()));
+    }
+    for (i, problem) in body.problems.iter().enumerate() {
+        if body.problems[..i].iter().any(|x| x.label == problem.label) {
+            return Err(Error::InvalidArgument(format!(
+                "Label {} is repeated in problems.",
+                problem.label
+            )));
+        }
+        if problem.max_score.is_some() && problem.weight.is_some() {
+            return Err(Error::InvalidArgument(format!(
+                "Problem {} has both max_score and weight.",
+                problem.label
+            )));
+        }
+        if let Some(x) = problem.max_score.or(problem.weight) {
+            if !x.is_finite() || x < 0.0 {
+                return Err(invalid_argument("score override", x));
+            }
+        }
+        if problem.submission_limit == Some(0) {
+            return Err(invalid_argument("submission_limit", 0));
+        }
+    }
+    if let Some(names) = body.allowed_languages.as_ref() {
+        if names.is_empty() {
+            return Err(Error::InvalidArgument(
+                "No language is allowed.".to_string(),
+            ));
+        }
+        if let Some(name) = names
+            .iter()
+            .find(|x| !config.languages.iter().any(|y| y.name == **x))
+        {
+            return Err(Error::NotFound(format!("Language {} not found.", name)));
+        }
+    }
+    no_repeats("user_ids", &body.user_ids)?;
+    let user_list = USER_LIST.lock().unwrap();
+    let missing = body
+        .user_ids
+        .iter()
+        .any(|x| !user_list.iter().any(|y| y.id == Some(*x as u32)));
     drop(user_list);
+    if missing {
+        return Err(Error::NotFound("User id not found".to_string()));
+    }
+    if body.submission_limit == 0 {
+        return Err(invalid_argument("submission_limit", 0));
+    }
 
-    if body.id.is_none() {
-        let id = contest_list.len();
-        contest_list.push(Contest {
-            id,
-            name: body.name.clone(),
-            from: body.from.clone(),
-            to: body.to.to_string(),
-            problem_ids: body.problem_ids.clone(),
-            user_ids: body.user_ids.clone(),
-            submission_limit: body.submission_limit,
-        });
-        drop(contest_list);
-        return HttpResponse::Ok().json(Contest {
-            id,
-            name: body.name.clone(),
-            from: body.from.clone(),
-            to: body.to.to_string(),
-            problem_ids: body.problem_ids.clone(),
-            user_ids: body.user_ids.clone(),
-            submission_limit: body.submission_limit,
-        });
-    } else {
-        if body.id.unwrap() >= contest_list.len() {
-            drop(contest_list);
-            return HttpResponse::NotFound().json(Error {
-                reason: "ERR_NOT_FOUND".to_string(),
-                code: 3,
-                message: format!("Contest {} not found", body.id.unwrap()).to_string(),
-            });
-        } else {
-            contest_list[body.id.unwrap()] = Contest {
-                id: body.id.unwrap(),
-                name: body.name.clone(),
-                from: body.from.clone(),
-                to: body.to.clone(),
-                problem_ids: body.problem_ids.clone(),
-                user_ids: body.user_ids.clone(),
-                submission_limit: body.submission_limit,
-            };
-            drop(contest_list);
-            return HttpResponse::Ok().json(Contest {
-                id: body.id.unwrap(),
-                name: body.name.clone(),
-                from: body.from.clone(),
-                to: body.to.clone(),
-                problem_ids: body.problem_ids.clone(),
-                user_ids: body.user_ids.clone(),
-                submission_limit: body.submission_limit,
-            });
+    if body.registration == Registration::InviteCode && body.invite_code.is_none() {
+        return Err(Error::InvalidArgument("Missing invite_code.".to_string()));
+    }
+    let registration_from = optional_time("registration_from", &body.registration_from)?;
+    let registration_to = optional_time("registration_to", &body.registration_to)?;
+    if let (Some(registration_from), Some(registration_to)) = (registration_from, registration_to) {
+        if registration_to <= registration_from {
+            return Err(Error::InvalidArgument(
+                "Registration must close after it opens.".to_string(),
+            ));
+        }
+    }
+    let freeze_time = optional_time("freeze_time", &body.freeze_time)?;
+    if let Some(freeze_time) = freeze_time {
+        if freeze_time < from || freeze_time > to {
+            return Err(Error::InvalidArgument(
+                "The freeze time must be within the contest.".to_string(),
+            ));
         }
     }
+
+    Ok(Times {
+        from: format_time(from),
+        to: format_time(to),
+        registration_from: registration_from.map(format_time),
+        registration_to: registration_to.map(format_time),
+        freeze_time: freeze_time.map(format_time),
+    })
+}
+
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Parse a time validated by `post_contest`, or generated by the server
+pub fn parse_time(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// Check that the teams exist in `teams` and no user is in two of them
+pub fn check_teams(team_ids: &[usize], teams: &[Team]) -> ApiResult<()> {
+    let mut members: Vec<u32> = Vec::new();
+    for id in team_ids {
+        let team = match teams.get(*id) {
+            Some(team) => team,
+            None => return Err(Error::NotFound(format!("Team {} not found.", id))),
+        };
+        if let Some(user_id) = team.member_ids.iter().find(|x| members.contains(x)) {
+            return Err(Error::InvalidArgument(format!(
+                "User {} is in more than one team.",
+                user_id
+            )));
+        }
+        members.extend(team.member_ids.iter());
+    }
+    Ok(())
 }
 
 #[get("/contests")]
-async fn get_contests() -> impl Responder {
+async fn get_contests(req: HttpRequest) -> ApiResult<HttpResponse> {
+    let admin = auth::access(&req).is_signed_admin();
     let contest_list = CONTEST_LIST.lock().unwrap();
     let res = HttpResponse::Ok().json(
         contest_list
-            .clone()
-            .into_iter()
+            .iter()
             .skip(1)
-            .collect::<Vec<Contest>>(),
+            .map(|x| x.view(admin))
+            .collect::<Vec<ContestView>>(),
     );
     drop(contest_list);
-    res
+    Ok(res)
 }
 
 #[get("/contests/{contest_id}")]
-async fn get_contests_by_id(contest_id: web::Path<usize>) -> impl Responder {
+async fn get_contests_by_id(
+    contest_id: web::Path<usize>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let admin = auth::access(&req).is_signed_admin();
     let contest_list = CONTEST_LIST.lock().unwrap();
-    if contest_id.clone() >= contest_list.len() {
+    if *contest_id >= contest_list.len() {
         drop(contest_list);
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: format!("Contest {} not found.", contest_id.clone()),
-        });
+        Err(Error::NotFound(format!(
+            "Contest {} not found.",
+            contest_id.clone()
+        )))
+    } else {
+        let res = HttpResponse::Ok().json(contest_list[*contest_id].view(admin));
+        drop(contest_list);
+        Ok(res)
+    }
+}
+
+/// Add the acting user, or its team in team contests, to a contest
+/// as far as the registration rules of the contest allow
+#[post("/contests/{contest_id}/register")]
+async fn register_contest(
+    contest_id: web::Path<usize>,
+    body: web::Json<Register>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let user_id = auth::acting_user(&req, body.user_id)? as usize;
+    crate::users::check_active(user_id as u32)?;
+    let admin = auth::access(&req).is_signed_admin();
+
+    let mut contest_list = CONTEST_LIST.lock().unwrap();
+    let contest = match contest_list.get_mut(*contest_id) {
+        Some(contest) if contest.id != 0 => contest,
+        _ => {
+            return Err(Error::NotFound(format!(
+                "Contest {} not found.",
+                contest_id
+            )))
+        }
+    };
+    let now = Utc::now();
+    if let Some(from) = contest.registration_from.as_ref() {
+        if now < parse_time(from) {
+            return Err(Error::InvalidState(format!(
+                "Registration of contest {} hasn't opened.",
+                contest_id
+            )));
+        }
+    }
+    if now > parse_time(contest.registration_to.as_ref().unwrap_or(&contest.to)) {
+        return Err(Error::InvalidState(format!(
+            "Registration of contest {} has closed.",
+            contest_id
+        )));
+    }
+
+    let id = if contest.teams {
+        let team_id = match body.team_id {
+            Some(team_id) => team_id as usize,
+            None => {
+                return Err(Error::InvalidArgument(
+                    "Missing team_id for a team contest.".to_string(),
+                ))
+            }
+        };
+        let members = match TEAM_LIST.lock().unwrap().get(team_id) {
+            Some(team) => team.member_ids.clone(),
+            None => return Err(Error::NotFound(format!("Team {} not found.", team_id))),
+        };
+        if !members.contains(&(user_id as u32)) && !admin {
+            return Err(Error::Forbidden(format!(
+                "User {} is not in team {}.",
+                user_id, team_id
+            )));
+        }
+        team_id
+    } else {
+        user_id
+    };
+
+    if !admin {
+        match contest.registration {
+            Registration::Open => {}
+            Registration::InviteCode => {
+                if body.code.is_none() || body.code != contest.invite_code {
+                    return Err(Error::Forbidden("Wrong invite code.".to_string()));
+                }
+            }
+            Registration::Approval => {
+                if !participants(contest).contains(&id) && !contest.pending_ids.contains(&id) {
+                    contest.pending_ids.push(id);
+                }
+                let res = HttpResponse::Ok().json(contest.view(admin));
+                drop(contest_list);
+                return Ok(res);
+            }
+            Registration::Closed => {
+                return Err(Error::Forbidden(format!(
+                    "Contest {} is closed for registration.",
+                    contest_id
+                )))
+            }
+        }
+    }
+    admit(contest, id)?;
+    let res = HttpResponse::Ok().json(contest.view(admin));
+    drop(contest_list);
+    Ok(res)
+}
+
+/// The user ids, or the team ids of team contests
+fn participants(contest: &Contest) -> &Vec<usize> {
+    if contest.teams {
+        &contest.team_ids
     } else {
-        let res = contest_list[contest_id.clone()].clone();
+        &contest.user_ids
+    }
+}
+
+/// Add a user or team to the participants, keeping them sorted like `post_contest` does
+fn admit(contest: &mut Contest, id: usize) -> ApiResult<()> {
+    contest.pending_ids.retain(|x| *x != id);
+    if contest.teams {
+        if let Err(i) = contest.team_ids.binary_search(&id) {
+            let mut team_ids = contest.team_ids.clone();
+            team_ids.insert(i, id);
+            check_teams(&team_ids, &TEAM_LIST.lock().unwrap())?;
+            contest.team_ids = team_ids;
+        }
+    } else if let Err(i) = contest.user_ids.binary_search(&id) {
+        contest.user_ids.insert(i, id);
+    }
+    Ok(())
+}
+
+/// Approve a user, or a team in team contests, into a contest
+#[put("/contests/{contest_id}/participants/{id}")]
+async fn approve_participant(path: web::Path<(usize, usize)>) -> ApiResult<HttpResponse> {
+    let (contest_id, id) = path.into_inner();
+    let mut contest_list = CONTEST_LIST.lock().unwrap();
+    let contest = match contest_list.get_mut(contest_id) {
+        Some(contest) if contest.id != 0 => contest,
+        _ => {
+            return Err(Error::NotFound(format!(
+                "Contest {} not found.",
+                contest_id
+            )))
+        }
+    };
+    if contest.teams {
+        if TEAM_LIST.lock().unwrap().get(id).is_none() {
+            return Err(Error::NotFound(format!("Team {} not found.", id)));
+        }
+    } else {
+        crate::users::check_active(id as u32)?;
+    }
+    admit(contest, id)?;
+    // Only admins get here
+    let res = HttpResponse::Ok().json(contest.view(true));
+    drop(contest_list);
+    Ok(res)
+}
+
+/// Remove a user, or a team in team contests, from a contest or its waiting list.
+/// Its jobs are kept.
+#[delete("/contests/{contest_id}/participants/{id}")]
+async fn kick_participant(path: web::Path<(usize, usize)>) -> ApiResult<HttpResponse> {
+    let (contest_id, id) = path.into_inner();
+    let mut contest_list = CONTEST_LIST.lock().unwrap();
+    let contest = match contest_list.get_mut(contest_id) {
+        Some(contest) if contest.id != 0 => contest,
+        _ => {
+            return Err(Error::NotFound(format!(
+                "Contest {} not found.",
+                contest_id
+            )))
+        }
+    };
+    if !participants(contest).contains(&id) && !contest.pending_ids.contains(&id) {
+        return Err(Error::NotFound(format!(
+            "{} {} is not in contest {}.",
+            if contest.teams { "Team" } else { "User" },
+            id,
+            contest_id
+        )));
+    }
+    contest.pending_ids.retain(|x| *x != id);
+    if contest.teams {
+        contest.team_ids.retain(|x| *x != id);
+    } else {
+        contest.user_ids.retain(|x| *x != id);
+    }
+    // Only admins get here
+    let res = HttpResponse::Ok().json(contest.view(true));
+    drop(contest_list);
+    Ok(res)
+}
+
+/// Stream every job update of the contest as server-sent events.
+/// Results hidden by the freeze are only streamed to admins.
+#[get("/contests/{contest_id}/events")]
+async fn get_contest_events(
+    contest_id: web::Path<u32>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let contest_list = CONTEST_LIST.lock().unwrap();
+    if *contest_id as usize >= contest_list.len() {
         drop(contest_list);
-        return HttpResponse::Ok().json(res);
+        return Err(Error::NotFound(format!(
+            "Contest {} not found.",
+            contest_id
+        )));
+    }
+    drop(contest_list);
+    Ok(events::response(events::subscribe(
+        Topic::Contest(*contest_id),
+        None,
+        auth::access(&req).is_signed_admin(),
+    )))
+}
+
+/// Reveal the results hidden by the freeze, all at once or one step at a time.
+/// Steps go up the icpc ranklist from the bottom,
+/// revealing the first problem with pending jobs of the lowest participant.
+#[post("/contests/{contest_id}/unfreeze")]
+async fn unfreeze_contest(
+    contest_id: web::Path<usize>,
+    body: web::Json<Unfreeze>,
+) -> ApiResult<HttpResponse> {
+    let contest = match CONTEST_LIST.lock().unwrap().get(*contest_id) {
+        Some(contest) if contest.id != 0 => contest.clone(),
+        _ => {
+            return Err(Error::NotFound(format!(
+                "Contest {} not found.",
+                contest_id
+            )))
+        }
+    };
+    if contest.freeze_time.is_none() {
+        return Err(Error::InvalidState(format!(
+            "Contest {} has no freeze time.",
+            contest_id
+        )));
+    }
+
+    let mut revealed: Vec<u32> = Vec::new();
+    if body.step {
+        let board = icpc_ranklist(&contest, false, &[])?;
+        let step = board.iter().rev().find_map(|row| {
+            let id = match (row.user.as_ref(), row.team.as_ref()) {
+                (Some(user), _) => user.id,
+                (_, Some(team)) => team.id,
+                _ => return None,
+            };
+            let problem = row.cells.iter().position(|x| x.pending > 0)?;
+            Some((id, contest.problem_ids[problem] as u32))
+        });
+        if let Some((id, problem_id)) = step {
+            let lock = RESPONSE_LIST.lock().unwrap();
+            revealed = lock
+                .iter()
+                .filter(|x| {
+                    let owner = if contest.teams {
+                        x.submission.team_id
+                    } else {
+                        Some(x.submission.user_id)
+                    };
+                    x.submission.contest_id as usize == contest.id
+                        && x.submission.problem_id == problem_id
+                        && owner == Some(id)
+                        && contest.hides(x)
+                })
+                .map(|x| x.id)
+                .collect();
+            drop(lock);
+        }
+    }
+
+    let mut contest_list = CONTEST_LIST.lock().unwrap();
+    let contest = &mut contest_list[*contest_id];
+    if revealed.is_empty() {
+        contest.unfrozen = true;
+    } else {
+        contest.revealed_ids.extend(revealed.iter());
+    }
+    let res = serde_json::json!({
+        "revealed_ids": revealed,
+        "unfrozen": contest.unfrozen,
+    });
+    drop(contest_list);
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(Deserialize)]
+struct Extend {
+    minutes: u32,
+}
+
+/// Change a contest with `action` under the contest list lock
+fn modify(
+    contest_id: usize,
+    action: impl FnOnce(&mut Contest, DateTime<Utc>) -> ApiResult<()>,
+) -> ApiResult<HttpResponse> {
+    let mut contest_list = CONTEST_LIST.lock().unwrap();
+    let contest = match contest_list.get_mut(contest_id) {
+        Some(contest) if contest.id != 0 => contest,
+        _ => {
+            return Err(Error::NotFound(format!(
+                "Contest {} not found.",
+                contest_id
+            )))
+        }
+    };
+    action(contest, Utc::now())?;
+    // Only admins get here
+    let res = HttpResponse::Ok().json(contest.view(true));
+    drop(contest_list);
+    Ok(res)
+}
+
+/// Move the end of a contest later, reopening it if it has ended
+#[post("/contests/{contest_id}/extend")]
+async fn extend_contest(
+    contest_id: web::Path<usize>,
+    body: web::Json<Extend>,
+) -> ApiResult<HttpResponse> {
+    if body.minutes == 0 {
+        return Err(Error::InvalidArgument("Invalid minutes '0'.".to_string()));
+    }
+    modify(*contest_id, |contest, _| {
+        contest.to = format_time(contest.end() + Duration::minutes(body.minutes as i64));
+        Ok(())
+    })
+}
+
+/// Stop accepting submissions until the contest is resumed
+#[post("/contests/{contest_id}/pause")]
+async fn pause_contest(contest_id: web::Path<usize>) -> ApiResult<HttpResponse> {
+    modify(*contest_id, |contest, now| match contest.state(now) {
+        State::Running | State::Frozen => {
+            contest.paused_at = Some(format_time(now));
+            Ok(())
+        }
+        _ => Err(Error::InvalidState(format!(
+            "Contest {} is not running.",
+            contest.id
+        ))),
+    })
+}
+
+/// Accept submissions again, extending the contest by the time it was paused
+#[post("/contests/{contest_id}/resume")]
+async fn resume_contest(contest_id: web::Path<usize>) -> ApiResult<HttpResponse> {
+    modify(*contest_id, |contest, now| {
+        let paused_at = match contest.paused_at.take() {
+            Some(paused_at) => parse_time(&paused_at),
+            None => {
+                return Err(Error::InvalidState(format!(
+                    "Contest {} is not paused.",
+                    contest.id
+                )))
+            }
+        };
+        if paused_at < contest.end() {
+            contest.to = format_time(contest.end() + (now - paused_at));
+        }
+        Ok(())
+    })
+}
+
+/// End a started contest now
+#[post("/contests/{contest_id}/end")]
+async fn end_contest(contest_id: web::Path<usize>) -> ApiResult<HttpResponse> {
+    modify(*contest_id, |contest, now| match contest.state(now) {
+        State::Upcoming => Err(Error::InvalidState(format!(
+            "Contest {} hasn't started.",
+            contest.id
+        ))),
+        State::Ended => Err(Error::InvalidState(format!(
+            "Contest {} has ended.",
+            contest.id
+        ))),
+        _ => {
+            contest.paused_at = None;
+            contest.to = format_time(now);
+            Ok(())
+        }
+    })
+}
+
+/// A team in a ranklist
+#[derive(Serialize)]
+struct TeamRow {
+    id: u32,
+    name: String,
+    /// Names of the members
+    members: Vec<String>,
+}
+
+impl TeamRow {
+    fn new(team: &Team, users: &[User]) -> Self {
+        TeamRow {
+            id: team.id,
+            name: team.name.clone(),
+            members: team
+                .member_ids
+                .iter()
+                .filter_map(|x| users.iter().find(|y| y.id == Some(*x)))
+                .map(|x| x.name.clone())
+                .collect(),
+        }
+    }
+}
+
+/// A problem of a participant in an icpc ranklist
+#[derive(Serialize, Clone, Default)]
+struct IcpcCell {
+    label: String,
+    /// Jobs counted until the first accepted one, including it
+    attempts: u32,
+    solved: bool,
+    /// Minutes from the start of the contest to the first accepted job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<i64>,
+    /// Jobs after the freeze, whose results are hidden
+    #[serde(skip_serializing_if = "is_zero")]
+    pending: u32,
+}
+
+fn is_zero(x: &u32) -> bool {
+    *x == 0
+}
+
+#[derive(Serialize)]
+struct IcpcRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<PublicUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team: Option<TeamRow>,
+    rank: u32,
+    solved: u32,
+    /// Minutes, the solve times plus the penalty of rejected jobs before them
+    penalty: i64,
+    cells: Vec<IcpcCell>,
+    submission_count: u32,
+    /// Ranked by a virtual run
+    #[serde(skip_serializing_if = "is_false")]
+    r#virtual: bool,
+}
+
+fn is_false(x: &bool) -> bool {
+    !*x
+}
+
+/// Rank the participants of a contest by solved problems, then by penalty.
+/// Cells follow the order of the contest problems.
+/// Unless `live`, jobs hidden by the freeze are only counted as pending.
+/// Users of `runs` are ranked too, by the jobs of their virtual runs.
+fn icpc_ranklist(contest: &Contest, live: bool, runs: &[VirtualRun]) -> ApiResult<Vec<IcpcRow>> {
+    let mut ids = participants(contest).clone();
+    let official = ids.len();
+    ids.extend(runs.iter().map(|x| x.user_id as usize));
+    let row: Vec<IcpcCell> = contest
+        .problems
+        .iter()
+        .map(|x| IcpcCell {
+            label: x.label.clone(),
+            ..Default::default()
+        })
+        .collect();
+    let mut cells = vec![row; ids.len()];
+    let mut submission_count = vec![0; ids.len()];
+
+    let lock = RESPONSE_LIST.lock().unwrap();
+    for job in lock.iter() {
+        if job.submission.contest_id as usize != contest.id || job.state != "Finished" {
+            continue;
+        }
+        let owner = if contest.teams {
+            job.submission.team_id.map(|x| x as usize)
+        } else {
+            Some(job.submission.user_id as usize)
+        };
+        let (rows, offset) = match job.submission.participation {
+            Participation::Official => (&ids[..official], 0),
+            Participation::Virtual => (&ids[official..], official),
+            Participation::Upsolving => continue,
+        };
+        let row = match owner.and_then(|x| rows.iter().position(|y| *y == x)) {
+            Some(row) => row + offset,
+            None => continue,
+        };
+        let problem = match contest
+            .problem_ids
+            .iter()
+            .position(|x| *x == job.submission.problem_id as usize)
+        {
+            Some(problem) => problem,
+            None => continue,
+        };
+        submission_count[row] += 1;
+        let cell = &mut cells[row][problem];
+        if cell.solved {
+            continue;
+        }
+        if !live && contest.hides(job) {
+            cell.pending += 1;
+            continue;
+        }
+        match job.result {
+            Result::Accepted => {
+                let time = contest.elapsed(job, runs).num_minutes().max(0);
+                cell.attempts += 1;
+                cell.solved = true;
+                cell.time = Some(time);
+            }
+            // Failures of the judge aren't the participant's fault
+            Result::SystemError => {}
+            Result::CompilationError if !contest.penalize_compilation_error => {}
+            _ => cell.attempts += 1,
+        }
+    }
+    drop(lock);
+
+    let users = USER_LIST.lock().unwrap().clone();
+    let teams = TEAM_LIST.lock().unwrap().clone();
+    let mut res: Vec<IcpcRow> = ids
+        .iter()
+        .enumerate()
+        .zip(cells)
+        .zip(submission_count)
+        .map(|(((i, id), cells), submission_count)| {
+            let solved = cells.iter().filter(|x| x.solved).count() as u32;
+            let penalty = cells
+                .iter()
+                .filter_map(|x| {
+                    x.time
+                        .map(|time| time + contest.penalty_minutes as i64 * (x.attempts as i64 - 1))
+                })
+                .sum();
+            let (user, team) = if contest.teams {
+                (None, teams.get(*id).map(|x| TeamRow::new(x, &users)))
+            } else {
+                (
+                    users
+                        .iter()
+                        .find(|x| x.id == Some(*id as u32))
+                        .map(PublicUser::from),
+                    None,
+                )
+            };
+            IcpcRow {
+                user,
+                team,
+                rank: 0,
+                solved,
+                penalty,
+                cells,
+                submission_count,
+                r#virtual: i >= official,
+            }
+        })
+        .collect();
+
+    // Stable, so tied participants stay in id order
+    res.sort_by(|a, b| b.solved.cmp(&a.solved).then(a.penalty.cmp(&b.penalty)));
+    for i in 0..res.len() {
+        res[i].rank = if i > 0
+            && res[i].solved == res[i - 1].solved
+            && res[i].penalty == res[i - 1].penalty
+        {
+            res[i - 1].rank
+        } else {
+            i as u32 + 1
+        };
     }
+    Ok(res)
 }
 
 #[get("/contests/{contest_id}/ranklist")]
@@ -180,42 +1219,91 @@ async fn get_contests_ranklist(
     contest_id: web::Path<u32>,
     info: web::Query<Info>,
     config: web::Data<Config>,
-) -> impl Responder {
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    if info.live && !auth::access(&req).is_signed_admin() {
+        return Err(Error::Forbidden(
+            "Only admins may see the live ranklist.".to_string(),
+        ));
+    }
+    if info.with_virtual && *contest_id == 0 {
+        return Err(Error::InvalidArgument(
+            "Virtual runs need a contest.".to_string(),
+        ));
+    }
+    let runs = if info.with_virtual {
+        virtuals::runs_of(*contest_id as usize)
+    } else {
+        Vec::new()
+    };
+    if info.scoring_rule.as_deref() == Some("icpc") {
+        let contest = match CONTEST_LIST.lock().unwrap().get(*contest_id as usize) {
+            Some(contest) if contest.id != 0 => contest.clone(),
+            Some(_) => {
+                return Err(Error::InvalidArgument(
+                    "ICPC scoring needs a contest.".to_string(),
+                ))
+            }
+            None => {
+                return Err(Error::NotFound(format!(
+                    "Contest {} not found.",
+                    contest_id
+                )))
+            }
+        };
+        return Ok(HttpResponse::Ok().json(icpc_ranklist(&contest, info.live, &runs)?));
+    }
     let problems_count = config.problems.len();
 
     let lock = crate::users::USER_LIST.lock().unwrap();
     let users = lock.clone();
     drop(lock);
+    let lock = CONTEST_LIST.lock().unwrap();
+    let contest = match lock.get(*contest_id as usize) {
+        Some(contest) if contest.id != 0 => Some(contest.clone()),
+        _ => None,
+    };
+    drop(lock);
+    // Team contests rank teams instead of users
+    let teams = matches!(contest.as_ref(), Some(contest) if contest.teams);
+    let team_list = TEAM_LIST.lock().unwrap().clone();
+    let count = if teams { team_list.len() } else { users.len() };
 
-    let mut users_score = vec![vec![(0 as f32, String::new()); problems_count]; users.len()];
-    let mut submission_count = vec![0; users.len()];
-    let mut latest_time = vec![String::new(); users.len()];
+    let mut users_score = vec![vec![(0 as f32, String::new()); problems_count]; count];
+    let mut submission_count = vec![0; count];
+    let mut pending = vec![0; count];
+    let mut latest_time = vec![String::new(); count];
+    // Best subtask scores by participant and problem
+    let mut subtask_max: HashMap<(usize, usize), Vec<f32>> = HashMap::new();
 
     let lock = crate::jobs::RESPONSE_LIST.lock().unwrap();
     let mut response_list = lock.clone();
     drop(lock);
 
-    if contest_id.clone() != 0 {
-        response_list = response_list
-            .into_iter()
-            .filter(|x| x.submission.contest_id == contest_id.clone())
-            .collect();
+    // Jobs not judged yet or canceled don't count
+    response_list.retain(|x| x.state == "Finished");
+    if *contest_id != 0 {
+        response_list.retain(|x| x.submission.contest_id == *contest_id);
     }
 
+    // Jobs refer to problems by id, the scores follow the order of the config
+    let index_of = |problem_id: u32| config.problems.iter().position(|x| x.id == problem_id);
+    response_list.retain(|x| index_of(x.submission.problem_id).is_some());
+
     for i in response_list.iter_mut() {
-        if config.problems[i.submission.problem_id as usize].r#type == "dynamic_ranking" {
-            if let Some(ratio) = config.problems[i.submission.problem_id as usize]
-                .misc
-                .dynamic_ranking_ratio
-                .as_ref()
-            {
+        let problem = &config.problems[index_of(i.submission.problem_id).unwrap()];
+        if problem.r#type == "dynamic_ranking" {
+            if let Some(ratio) = problem.misc.dynamic_ranking_ratio.as_ref() {
                 let mut new_score: f32 = 0.0;
                 if i.result == Result::Accepted {
-                    let mut shortest = vec![0 as u128; i.score_vec.len()];
+                    let mut shortest = vec![0_u128; i.score_vec.len()];
                     let lock = RESPONSE_LIST.lock().unwrap();
-                    for i in lock.iter() {
-                        if i.submission.problem_id == i.submission.problem_id as u32 {
-                            for j in i.cases.iter().enumerate().skip(1) {
+                    // Shortest times among accepted jobs of the same problem only
+                    for k in lock.iter() {
+                        if k.submission.problem_id == i.submission.problem_id
+                            && k.result == Result::Accepted
+                        {
+                            for j in k.cases.iter().enumerate().skip(1) {
                                 if shortest[j.0 - 1] == 0 || j.1.time < shortest[j.0 - 1] {
                                     shortest[j.0 - 1] = j.1.time;
                                 }
@@ -224,7 +1312,7 @@ async fn get_contests_ranklist(
                     }
                     for (u, s) in i.score_vec.iter().enumerate() {
                         new_score += *s
-                            * (1 as f32 - *ratio
+                            * (1_f32 - *ratio
                                 + *ratio * (shortest[u] as f32) / (i.cases[u + 1].time as f32));
                     }
                     drop(lock);
@@ -237,35 +1325,86 @@ async fn get_contests_ranklist(
     }
 
     for i in response_list.iter() {
-        if contest_id.clone() != 0 {
-            if i.submission.contest_id != contest_id.clone() {
+        if *contest_id != 0 && i.submission.contest_id != *contest_id {
+            continue;
+        }
+        // Contest ranklists leave out upsolving, and virtual runs unless asked for
+        match i.submission.participation {
+            Participation::Official => {}
+            Participation::Virtual if info.with_virtual => {}
+            _ if *contest_id == 0 => {}
+            _ => continue,
+        }
+        let time = match (contest.as_ref(), i.submission.participation) {
+            (Some(contest), Participation::Virtual) => {
+                format_time(contest.start() + contest.elapsed(i, &runs))
+            }
+            _ => i.created_time.clone(),
+        };
+        let owner = if teams {
+            match i.submission.team_id {
+                Some(team_id) => team_id as usize,
+                None => continue,
+            }
+        } else {
+            i.submission.user_id as usize
+        };
+        let problem = index_of(i.submission.problem_id).unwrap();
+        submission_count[owner] += 1;
+        if let Some(contest) = contest.as_ref() {
+            if !info.live && contest.hides(i) {
+                pending[owner] += 1;
                 continue;
             }
         }
-        submission_count[i.submission.user_id as usize] += 1;
-        if info.scoring_rule.is_some() && info.scoring_rule.clone().unwrap().as_str() == "highest" {
-            if i.score
-                > users_score[i.submission.user_id as usize][i.submission.problem_id as usize].0
-            {
-                users_score[i.submission.user_id as usize][i.submission.problem_id as usize] =
-                    (i.score, i.created_time.clone());
+        match info.scoring_rule.as_deref() {
+            Some("highest") => {
+                if i.score > users_score[owner][problem].0 {
+                    users_score[owner][problem] = (i.score, time);
+                }
             }
-        } else {
-            if i.created_time
-                > users_score[i.submission.user_id as usize][i.submission.problem_id as usize].1
-                || users_score[i.submission.user_id as usize][i.submission.problem_id as usize]
-                    .1
-                    .is_empty()
-            {
-                users_score[i.submission.user_id as usize][i.submission.problem_id as usize] =
-                    (i.score, i.created_time.clone());
+            // Sum the best score of each subtask over every job,
+            // the time is of the last job improving one of them
+            Some("ioi_subtask_max") => {
+                let best = subtask_max.entry((owner, problem)).or_default();
+                if best.len() < i.subtask_scores.len() {
+                    best.resize(i.subtask_scores.len(), 0.0);
+                }
+                let mut improved = false;
+                for (best, score) in best.iter_mut().zip(i.subtask_scores.iter()) {
+                    if *score > *best {
+                        *best = *score;
+                        improved = true;
+                    }
+                }
+                let cell = &mut users_score[owner][problem];
+                cell.0 = best.iter().sum();
+                if improved || cell.1.is_empty() {
+                    cell.1 = time;
+                }
+            }
+            _ => {
+                if time > users_score[owner][problem].1 || users_score[owner][problem].1.is_empty()
+                {
+                    users_score[owner][problem] = (i.score, time);
+                }
+            }
+        }
+    }
+
+    if let Some(contest) = contest.as_ref() {
+        for problem in contest.problems.iter() {
+            let index = index_of(problem.id as u32).unwrap();
+            let scale = problem.scale(&config);
+            for row in users_score.iter_mut() {
+                row[index].0 *= scale;
             }
         }
     }
 
-    let mut total_score = vec![(0 as f32, 0 as usize); users.len()];
+    let mut total_score = vec![(0 as f32, 0_usize); count];
     for (i, j) in users_score.iter().enumerate() {
-        let mut score = 0.0 as f32;
+        let mut score = 0.0_f32;
         for x in j.iter() {
             score += x.0;
         }
@@ -316,73 +1455,88 @@ async fn get_contests_ranklist(
     }
     #[derive(Serialize)]
     struct Res {
-        user: User,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user: Option<PublicUser>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        team: Option<TeamRow>,
         rank: u32,
         scores: Vec<f32>,
         submission_count: u32,
+        /// Jobs after the freeze, whose results are hidden
+        #[serde(skip_serializing_if = "is_zero")]
+        pending: u32,
+        /// Ranked by a virtual run
+        #[serde(skip_serializing_if = "is_false")]
+        r#virtual: bool,
     }
     let mut res: Vec<Res> = Vec::new();
 
     let mut last_score = 0 as f32;
     let mut last_id = 0;
     let mut rank = 1;
-    let mut totals = 0;
 
-    for j in total_score.iter() {
+    for (totals, j) in total_score.iter().enumerate() {
         match info.tie_breaker.as_deref() {
             Some("submission_count") => {
                 if (j.0 != last_score || submission_count[j.1] != submission_count[last_id])
                     && totals != 0
                 {
-                    rank = totals + 1;
+                    rank = totals as u32 + 1;
                 }
             }
             Some("submission_time") => {
                 if (j.0 != last_score || latest_time[j.1] != latest_time[last_id]) && totals != 0 {
-                    rank = totals + 1;
+                    rank = totals as u32 + 1;
                 }
             }
             Some("user_id") => {
                 if totals != 0 {
-                    rank = totals + 1;
+                    rank = totals as u32 + 1;
                 }
             }
             _ => {
                 if j.0 != last_score && totals != 0 {
-                    rank = totals + 1;
+                    rank = totals as u32 + 1;
                 }
             }
         }
         last_score = j.0;
         last_id = j.1;
+        let (user, team) = if teams {
+            (None, Some(TeamRow::new(&team_list[j.1], &users)))
+        } else {
+            (Some(PublicUser::from(&users[j.1])), None)
+        };
+        let r#virtual = !teams && runs.iter().any(|x| Some(x.user_id) == users[j.1].id);
         res.push(Res {
-            user: users[j.1].clone(),
+            user,
+            team,
             rank,
             scores: users_score[j.1].iter().map(|x| x.0).collect(),
             submission_count: submission_count[j.1],
+            pending: pending[j.1],
+            r#virtual,
         });
-        totals += 1;
     }
 
-    if contest_id.clone() != 0 {
+    if *contest_id != 0 {
         let contest_list = CONTEST_LIST.lock().unwrap();
         // score of user who is not in the contest must be 0, so remove these users will not change the rank
-        res = res
-            .into_iter()
-            .filter(|x| {
-                contest_list[contest_id.clone() as usize]
-                    .user_ids
-                    .contains(&(x.user.id.unwrap() as usize))
-            })
-            .collect();
+        let contest = &contest_list[*contest_id as usize];
+        res.retain(|x| match (x.user.as_ref(), x.team.as_ref()) {
+            (Some(user), _) => contest.user_ids.contains(&(user.id as usize)) || x.r#virtual,
+            (_, Some(team)) => contest.team_ids.contains(&(team.id as usize)),
+            _ => false,
+        });
+        // Scores follow the order of the contest problems
         for i in res.iter_mut() {
             let mut tmp: Vec<f32> = Vec::new();
-            for j in contest_list[contest_id.clone() as usize].problem_ids.iter() {
-                tmp.push(i.scores[*j]);
+            for j in contest.problems.iter() {
+                tmp.push(i.scores[index_of(j.id as u32).unwrap()]);
             }
             i.scores = tmp;
         }
     }
 
-    HttpResponse::Ok().json(res)
+    Ok(HttpResponse::Ok().json(res))
 }
@@ -0,0 +1,113 @@
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+/// An error of any api, sent as `{ reason, code, message }`
+#[derive(Debug)]
+pub enum Error {
+    InvalidArgument(String),
+    InvalidState(String),
+    NotFound(String),
+    RateLimit(String),
+    /// Missing, invalid or expired token
+    Unauthorized(String),
+    /// The token belongs to another user
+    Forbidden(String),
+    /// A tool the judge depends on, like a compiler, failed
+    External(String),
+    Internal(String),
+}
+
+pub type ApiResult<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize)]
+struct Body<'a> {
+    reason: &'a str,
+    code: u8,
+    message: &'a str,
+}
+
+impl Error {
+    fn reason(&self) -> &'static str {
+        match self {
+            Error::InvalidArgument(_) => "ERR_INVALID_ARGUMENT",
+            Error::InvalidState(_) => "ERR_INVALID_STATE",
+            Error::NotFound(_) => "ERR_NOT_FOUND",
+            Error::RateLimit(_) => "ERR_RATE_LIMIT",
+            Error::Unauthorized(_) => "ERR_UNAUTHORIZED",
+            Error::Forbidden(_) => "ERR_FORBIDDEN",
+            Error::External(_) => "ERR_EXTERNAL",
+            Error::Internal(_) => "ERR_INTERNAL",
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            Error::InvalidArgument(_) => 1,
+            Error::InvalidState(_) => 2,
+            Error::NotFound(_) => 3,
+            Error::RateLimit(_) => 4,
+            Error::External(_) => 5,
+            Error::Internal(_) => 6,
+            Error::Unauthorized(_) => 7,
+            Error::Forbidden(_) => 8,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Error::InvalidArgument(message)
+            | Error::InvalidState(message)
+            | Error::NotFound(message)
+            | Error::RateLimit(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::External(message)
+            | Error::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.reason(), self.message())
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::InvalidArgument(_) | Error::InvalidState(_) | Error::RateLimit(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::External(_) | Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(Body {
+            reason: self.reason(),
+            code: self.code(),
+            message: self.message(),
+        })
+    }
+}
+
+/// Malformed json bodies
+pub fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    Error::InvalidArgument(err.to_string()).into()
+}
+
+/// Malformed query strings
+pub fn query_error(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    Error::InvalidArgument(err.to_string()).into()
+}
+
+/// Malformed path segments, such as a job id that is not a number
+pub fn path_error(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    Error::InvalidArgument(err.to_string()).into()
+}
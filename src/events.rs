@@ -0,0 +1,148 @@
+use actix_web::{web::Bytes, HttpResponse};
+use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use crate::clarifications::{Clarification, Reader};
+use crate::jobs::{Response, Result};
+
+/// What a stream listens to
+#[derive(Clone, Copy)]
+pub enum Topic {
+    Job(u32),
+    /// Every job of a contest, contest 0 means all jobs
+    Contest(u32),
+    /// Clarifications of a contest the reader may read
+    Clarifications(u32, Reader),
+}
+
+struct Subscriber {
+    topic: Topic,
+    sender: UnboundedSender<Bytes>,
+    /// Reads results hidden by a frozen ranklist
+    live: bool,
+}
+
+/// A job update in a contest stream, without the source code of other users.
+/// Also sent in place of the job to job streams not reading its hidden results.
+#[derive(Serialize)]
+struct ContestUpdate<'a> {
+    id: u32,
+    user_id: u32,
+    problem_id: u32,
+    state: &'a str,
+    /// Absent while a frozen ranklist hides them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Result>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f32>,
+    updated_time: &'a str,
+}
+
+impl<'a> ContestUpdate<'a> {
+    fn new(job: &'a Response, results: bool) -> Self {
+        ContestUpdate {
+            id: job.id,
+            user_id: job.submission.user_id,
+            problem_id: job.submission.problem_id,
+            state: &job.state,
+            result: if results { Some(job.result) } else { None },
+            score: if results { Some(job.score) } else { None },
+            updated_time: &job.updated_time,
+        }
+    }
+}
+
+lazy_static! {
+    static ref SUBSCRIBERS: Arc<Mutex<Vec<Subscriber>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// Format a server-sent event
+fn event(name: &str, data: &impl Serialize) -> Bytes {
+    Bytes::from(format!(
+        "event: {}\ndata: {}\n\n",
+        name,
+        serde_json::to_string(data).unwrap()
+    ))
+}
+
+fn finished(job: &Response) -> bool {
+    job.state == "Finished" || job.state == "Canceled"
+}
+
+/// The job as sent to a job stream, only with its state while its results are hidden
+fn job_event(job: &Response, hidden: bool) -> Bytes {
+    if hidden {
+        event("job", &ContestUpdate::new(job, false))
+    } else {
+        event("job", job)
+    }
+}
+
+/// Start listening to `topic`, sending `current` first when given.
+/// Unless `live`, results hidden by a frozen ranklist are left out.
+/// A job stream ends once the job is finished or canceled.
+/// Call while holding `RESPONSE_LIST`, so no update is lost in between.
+pub fn subscribe(topic: Topic, current: Option<&Response>, live: bool) -> UnboundedReceiver<Bytes> {
+    let (sender, receiver) = mpsc::unbounded();
+    if let Some(job) = current {
+        let hidden = !live && crate::contests::hidden(job);
+        sender.unbounded_send(job_event(job, hidden)).ok();
+        if finished(job) {
+            return receiver;
+        }
+    }
+    SUBSCRIBERS.lock().unwrap().push(Subscriber {
+        topic,
+        sender,
+        live,
+    });
+    receiver
+}
+
+/// Send a changed job to its streams, dropping closed ones.
+/// Call while holding `RESPONSE_LIST`, so updates keep their order.
+pub fn publish(job: &Response) {
+    let hidden = crate::contests::hidden(job);
+    let mut lock = SUBSCRIBERS.lock().unwrap();
+    lock.retain(|x| match x.topic {
+        Topic::Job(id) if id == job.id => {
+            x.sender
+                .unbounded_send(job_event(job, hidden && !x.live))
+                .is_ok()
+                && !finished(job)
+        }
+        Topic::Contest(id) if id == 0 || id == job.submission.contest_id => x
+            .sender
+            .unbounded_send(event("job", &ContestUpdate::new(job, !hidden || x.live)))
+            .is_ok(),
+        _ => !x.sender.is_closed(),
+    });
+}
+
+/// Send a new or answered clarification to the streams of its readers.
+/// Call while holding the clarification list, so updates keep their order.
+pub fn publish_clarification(clarification: &Clarification) {
+    let mut lock = SUBSCRIBERS.lock().unwrap();
+    lock.retain(|x| match x.topic {
+        Topic::Clarifications(id, reader)
+            if id == clarification.contest_id && clarification.readable_by(reader) =>
+        {
+            x.sender
+                .unbounded_send(event("clarification", clarification))
+                .is_ok()
+        }
+        _ => !x.sender.is_closed(),
+    });
+}
+
+/// Turn a subscription into an event stream response
+pub fn response(receiver: UnboundedReceiver<Bytes>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(receiver.map(Ok::<_, Infallible>))
+}
@@ -1,34 +1,61 @@
-use actix_web::{get, post, put, web, HttpResponse, Responder};
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse};
 use chrono::prelude::*;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use std::{
-    fs,
-    sync::{Arc, Mutex},
-};
+use std::collections::VecDeque;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex};
 
+use crate::auth;
+use crate::contests::State;
+use crate::error::{ApiResult, Error};
+use crate::events::{self, Topic};
 use crate::{config::Config, users::USER_LIST};
 
 #[derive(Deserialize, Serialize)]
 struct PostJob {
     source_code: String,
     language: String,
-    user_id: u32,
+    /// Taken from the token when logged in
+    user_id: Option<u32>,
     contest_id: u32,
     problem_id: u32,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Response {
-    id: u32,
+    pub id: u32,
     pub created_time: String,
-    updated_time: String,
+    pub updated_time: String,
     pub submission: Submission,
-    state: String,
+    pub state: String,
     pub result: Result,
     pub score: f32,
     pub score_vec: Vec<f32>,
+    /// Score of each subtask, for scoring rules combining jobs
+    #[serde(default)]
+    pub subtask_scores: Vec<f32>,
     pub cases: Vec<Case>,
+    /// Every judgement of this job, oldest first
+    #[serde(skip)]
+    pub history: Vec<Attempt>,
+    /// Bumped whenever the job is canceled or requeued,
+    /// so the judging thread drops results of a run that no longer counts
+    #[serde(skip)]
+    pub attempt: u32,
+}
+
+/// A finished judgement of a job
+#[derive(Serialize, Clone)]
+pub struct Attempt {
+    time: String,
+    /// Version of the problem config and test data used
+    version: String,
+    result: Result,
+    score: f32,
+    cases: Vec<Case>,
 }
 
 lazy_static! {
@@ -38,10 +65,34 @@ lazy_static! {
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Submission {
     source_code: String,
-    language: String,
+    pub language: String,
     pub user_id: u32,
     pub contest_id: u32,
     pub problem_id: u32,
+    /// The team of the user in team contests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Participation::is_official")]
+    pub participation: Participation,
+}
+
+/// How a job takes part in its contest
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Participation {
+    /// Counted in the contest ranklist
+    #[default]
+    Official,
+    /// In a virtual run of the user, counted in the virtual ranklist only
+    Virtual,
+    /// After the contest, counted in no contest ranklist
+    Upsolving,
+}
+
+impl Participation {
+    fn is_official(&self) -> bool {
+        *self == Participation::Official
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -51,13 +102,24 @@ pub struct Case {
     pub time: u128,
     pub memory: u32,
     pub info: String,
+    /// Only reported for sample cases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SampleOutput>,
 }
 
-#[derive(Deserialize, Serialize)]
-struct Error {
-    reason: String,
-    code: u8,
-    message: String,
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SampleOutput {
+    pub expected_output: String,
+    pub actual_output: String,
+    /// None when the outputs match
+    pub first_difference: Option<Difference>,
+}
+
+/// 1-based position of the first difference between the outputs
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Difference {
+    pub line: usize,
+    pub column: usize,
 }
 
 #[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
@@ -81,52 +143,221 @@ pub enum Result {
 
     Waiting,
 
+    Running,
+
     Skipped,
+
+    Canceled,
+
+    #[serde(rename = "System Error")]
+    SystemError,
 }
 
+/// Job filter as sent by the client, checked by `Filter::parse`.
+/// Numbers are kept as text, so a malformed value can be reported with its field name.
 #[derive(Deserialize)]
 struct Info {
-    user_id: Option<u32>,
+    #[serde(default, deserialize_with = "text")]
+    user_id: Option<String>,
     user_name: Option<String>,
-    contest_id: Option<u32>,
-    problem_id: Option<u32>,
+    #[serde(default, deserialize_with = "text")]
+    contest_id: Option<String>,
+    #[serde(default, deserialize_with = "text")]
+    problem_id: Option<String>,
+    /// Comma separated languages
     language: Option<String>,
     from: Option<String>,
     to: Option<String>,
     state: Option<String>,
+    /// Comma separated results
+    result: Option<String>,
+    #[serde(default, deserialize_with = "text")]
+    score_min: Option<String>,
+    #[serde(default, deserialize_with = "text")]
+    score_max: Option<String>,
+    #[serde(default, deserialize_with = "text")]
+    id_from: Option<String>,
+    #[serde(default, deserialize_with = "text")]
+    id_to: Option<String>,
+}
+
+/// Accept both strings (query) and numbers (json body)
+fn text<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Text {
+        String(String),
+        Number(serde_json::Number),
+    }
+    Ok(Option::<Text>::deserialize(deserializer)?.map(|x| match x {
+        Text::String(s) => s,
+        Text::Number(n) => n.to_string(),
+    }))
+}
+
+/// A checked job filter, every condition given must hold
+#[derive(Default)]
+struct Filter {
+    user_ids: Option<Vec<u32>>,
+    contest_id: Option<u32>,
+    problem_id: Option<u32>,
+    languages: Option<Vec<String>>,
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+    state: Option<String>,
+    results: Option<Vec<Result>>,
+    score_min: Option<f32>,
+    score_max: Option<f32>,
+    id_from: Option<u32>,
+    id_to: Option<u32>,
+}
+
+/// Jobs queued by a bulk rejudge
+#[derive(Serialize, Clone)]
+struct Rejudge {
+    id: u32,
+    created_time: String,
+    job_ids: Vec<u32>,
+    /// Result and score of each job before the rejudge
+    #[serde(skip)]
+    previous: Vec<(Result, f32)>,
+}
+
+/// Effect of a bulk rejudge on the jobs finished so far
+#[derive(Serialize)]
+struct RejudgeSummary {
+    id: u32,
+    created_time: String,
+    job_ids: Vec<u32>,
+    finished: u32,
+    /// Finished jobs whose result or score changed
+    changed: Vec<Change>,
+}
+
+#[derive(Serialize)]
+struct Change {
+    id: u32,
+    previous_result: Result,
+    previous_score: f32,
+    result: Result,
+    score: f32,
+}
+
+/// Paging and output options of the job list
+#[derive(Deserialize)]
+struct Page {
+    /// created_time (default), updated_time, score or id
+    sort_by: Option<String>,
+    /// asc (default) or desc
+    order: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    /// Comma separated fields left out of each job: source_code, cases
+    omit: Option<String>,
+}
+
+/// A job in the job list, borrowed from `RESPONSE_LIST`
+#[derive(Serialize)]
+struct Listed<'a> {
+    id: u32,
+    created_time: &'a str,
+    updated_time: &'a str,
+    submission: ListedSubmission<'a>,
+    state: &'a str,
+    /// Absent while a frozen ranklist hides them from the requester
+    #[serde(skip_serializing_if = "Option::is_none")]
     result: Option<Result>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score_vec: Option<&'a [f32]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subtask_scores: Option<&'a [f32]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cases: Option<&'a [Case]>,
+}
+
+impl<'a> Listed<'a> {
+    fn new(job: &'a Response, source_code: bool, cases: bool) -> Self {
+        Listed {
+            id: job.id,
+            created_time: &job.created_time,
+            updated_time: &job.updated_time,
+            submission: ListedSubmission {
+                source_code: if source_code {
+                    Some(&job.submission.source_code)
+                } else {
+                    None
+                },
+                language: &job.submission.language,
+                user_id: job.submission.user_id,
+                contest_id: job.submission.contest_id,
+                problem_id: job.submission.problem_id,
+                team_id: job.submission.team_id,
+                participation: job.submission.participation,
+            },
+            state: &job.state,
+            result: Some(job.result),
+            score: Some(job.score),
+            score_vec: Some(&job.score_vec),
+            subtask_scores: Some(&job.subtask_scores),
+            cases: if cases { Some(&job.cases) } else { None },
+        }
+    }
+
+    /// Leave out everything judged, keeping the state
+    fn hide_results(self) -> Self {
+        Listed {
+            result: None,
+            score: None,
+            score_vec: None,
+            subtask_scores: None,
+            cases: None,
+            ..self
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ListedSubmission<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_code: Option<&'a str>,
+    language: &'a str,
+    user_id: u32,
+    contest_id: u32,
+    problem_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team_id: Option<u32>,
+    #[serde(skip_serializing_if = "Participation::is_official")]
+    participation: Participation,
 }
 
 lazy_static! {
     static ref JOB_ID: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
+    /// Ids of jobs waiting for the judging thread
+    static ref JOB_QUEUE: Arc<(Mutex<VecDeque<u32>>, Condvar)> =
+        Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+    static ref REJUDGE_LIST: Arc<Mutex<Vec<Rejudge>>> = Arc::new(Mutex::new(Vec::new()));
 }
 
 #[post("/jobs")]
-async fn post_jobs(body: web::Json<PostJob>, config: web::Data<Config>) -> impl Responder {
-    fs::remove_dir_all("./TMPDIR").err();
-
-    let created_time: String = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+async fn post_jobs(
+    body: web::Json<PostJob>,
+    req: HttpRequest,
+    config: web::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    let user_id = auth::acting_user(&req, body.user_id)?;
+    let now = Utc::now();
+    let created_time: String = now.to_rfc3339_opts(SecondsFormat::Millis, true);
     if !config.languages.iter().any(|x| x.name == body.language) {
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: "".to_string(),
-        });
+        return Err(Error::NotFound(format!(
+            "Language {} not found.",
+            body.language
+        )));
     }
-    let lock = crate::users::USER_LIST.lock().unwrap();
-    if lock
-        .iter()
-        .find(|x| x.id.unwrap() == body.user_id)
-        .is_none()
-    {
-        drop(lock);
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: "".to_string(),
-        });
-    }
-    drop(lock);
+    crate::users::check_active(user_id)?;
     let mut problem_id: usize = 0;
     for i in config.problems.iter().enumerate() {
         if i.1.id == body.problem_id {
@@ -134,325 +365,700 @@ async fn post_jobs(body: web::Json<PostJob>, config: web::Data<Config>) -> impl
         }
     }
     if !config.problems.iter().any(|x| x.id == body.problem_id) {
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: "".to_string(),
-        });
+        return Err(Error::NotFound(format!(
+            "Problem {} not found.",
+            body.problem_id
+        )));
+    }
+    if !config.problems[problem_id].allows(&body.language) {
+        return Err(Error::InvalidArgument(format!(
+            "Language {} is not allowed for problem {}.",
+            body.language, body.problem_id
+        )));
     }
 
+    let mut team_id = None;
+    let mut participation = Participation::Official;
     if body.contest_id != 0 {
-        let contest_list = crate::contests::CONTEST_LIST.lock().unwrap();
-        if body.contest_id as usize >= contest_list.len() {
-            drop(contest_list);
-            return HttpResponse::BadRequest().json(Error {
-                reason: "ERR_INVALID_ARGUMENT".to_string(),
-                code: 1,
-                message: "Contest doesn't exist.".to_string(),
-            });
-        }
-        if !contest_list[body.contest_id as usize]
-            .problem_ids
-            .contains(&(body.problem_id as usize))
+        // A copy, so jobs are never locked while holding the contest list
+        let contest = match crate::contests::CONTEST_LIST
+            .lock()
+            .unwrap()
+            .get(body.contest_id as usize)
         {
-            drop(contest_list);
-            return HttpResponse::BadRequest().json(Error {
-                reason: "ERR_INVALID_ARGUMENT".to_string(),
-                code: 1,
-                message: "Problem doesn't exist in this contest.".to_string(),
-            });
+            Some(contest) => contest.clone(),
+            None => return Err(Error::InvalidArgument("Contest doesn't exist.".to_string())),
+        };
+        if !contest.problem_ids.contains(&(body.problem_id as usize)) {
+            return Err(Error::InvalidArgument(
+                "Problem doesn't exist in this contest.".to_string(),
+            ));
         }
-        if !contest_list[body.contest_id as usize]
-            .user_ids
-            .contains(&(body.user_id as usize))
-        {
-            drop(contest_list);
-            return HttpResponse::BadRequest().json(Error {
-                reason: "ERR_INVALID_ARGUMENT".to_string(),
-                code: 1,
-                message: "User id is not in the contest.".to_string(),
-            });
+        if !contest.allows(&body.language) {
+            return Err(Error::InvalidArgument(format!(
+                "Language {} is not allowed in contest {}.",
+                body.language, body.contest_id
+            )));
         }
-
-        let response_list = RESPONSE_LIST.lock().unwrap();
-        let mut submission_count = 0;
-        for i in response_list.iter() {
-            if i.submission.contest_id == body.contest_id
-                && i.submission.problem_id == body.problem_id
-                && i.submission.user_id == body.user_id
-            {
-                submission_count += 1;
+        // After the end, jobs go to a virtual run of the user or to upsolving
+        let state = contest.state(now);
+        if state == State::Ended {
+            if crate::virtuals::active_run(contest.id, user_id, now).is_some() {
+                participation = Participation::Virtual;
+            } else if contest.upsolving {
+                participation = Participation::Upsolving;
             }
         }
-        drop(response_list);
-        if submission_count >= contest_list[body.contest_id as usize].submission_limit {
-            drop(contest_list);
-            return HttpResponse::BadRequest().json(Error {
-                reason: "ERR_RATE_LIMIT".to_string(),
-                code: 4,
-                message: "Over submission limit.".to_string(),
-            });
-        }
-        if created_time > contest_list[body.contest_id as usize].to
-            || created_time < contest_list[body.contest_id as usize].from
-        {
-            drop(contest_list);
-            return HttpResponse::BadRequest().json(Error {
-                reason: "ERR_RATE_LIMIT".to_string(),
-                code: 4,
-                message: "Time limit.".to_string(),
-            });
+        if participation == Participation::Official {
+            let registered = if contest.teams {
+                team_id = crate::teams::team_of(&contest.team_ids, user_id);
+                team_id.is_some()
+            } else {
+                contest.user_ids.contains(&(user_id as usize))
+            };
+            if !registered {
+                return Err(Error::InvalidArgument(
+                    "User id is not in the contest.".to_string(),
+                ));
+            }
         }
-        drop(contest_list);
-    }
 
-    let res = crate::judger::judger(&body.source_code, problem_id, &body.language, &config);
-    let updated_time: String = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
-
-    let mut result: Result;
-    if res.0[0].result == Result::CompilationError {
-        result = Result::CompilationError;
-    } else {
-        result = Result::Accepted;
-        for i in res.0.iter().skip(1) {
-            if i.result != Result::Accepted {
-                result = i.result;
-                break;
+        // In team contests the limit is shared by the team, upsolving has none
+        if participation != Participation::Upsolving {
+            let response_list = RESPONSE_LIST.lock().unwrap();
+            let mut submission_count = 0;
+            for i in response_list.iter() {
+                let same_owner = match team_id {
+                    Some(_) => i.submission.team_id == team_id,
+                    None => i.submission.user_id == user_id,
+                };
+                if i.submission.contest_id == body.contest_id
+                    && i.submission.problem_id == body.problem_id
+                    && i.submission.participation == participation
+                    && same_owner
+                    && i.state != "Canceled"
+                {
+                    submission_count += 1;
+                }
+            }
+            drop(response_list);
+            if submission_count >= contest.limit_of(body.problem_id) {
+                return Err(Error::RateLimit("Over submission limit.".to_string()));
+            }
+        }
+        if participation == Participation::Official {
+            match state {
+                State::Upcoming | State::Ended => {
+                    return Err(Error::RateLimit("Time limit.".to_string()));
+                }
+                State::Paused => {
+                    return Err(Error::InvalidState(format!(
+                        "Contest {} is paused.",
+                        body.contest_id
+                    )));
+                }
+                State::Running | State::Frozen => {}
             }
         }
     }
 
-    let score: f32 = res.1 .0;
-
-    let mut lock = JOB_ID.lock().unwrap();
-    let id = lock.clone();
-    *lock += 1;
-    drop(lock);
-
     let mut lock = RESPONSE_LIST.lock().unwrap();
-    lock.push(Response {
+    let mut job_id = JOB_ID.lock().unwrap();
+    let id = *job_id;
+    *job_id += 1;
+    drop(job_id);
+    let response = Response {
         id,
         created_time: created_time.clone(),
-        updated_time: updated_time.clone(),
+        updated_time: created_time,
         submission: Submission {
             source_code: body.source_code.clone(),
             language: body.language.clone(),
-            user_id: body.user_id,
+            user_id,
             contest_id: body.contest_id,
             problem_id: body.problem_id,
+            team_id,
+            participation,
         },
-        state: "Finished".to_string(),
-        result,
-        score,
-        score_vec: res.1 .1.clone(),
-        cases: res.0.clone(),
-    });
+        state: "Queueing".to_string(),
+        result: Result::Waiting,
+        score: 0.0,
+        score_vec: Vec::new(),
+        subtask_scores: Vec::new(),
+        cases: waiting_cases(config.problems[problem_id].cases.len()),
+        history: Vec::new(),
+        attempt: 0,
+    };
+    changed(&response);
+    lock.push(response.clone());
+    enqueue(id);
     drop(lock);
 
-    HttpResponse::Ok().json(Response {
-        id,
-        created_time,
-        updated_time,
-        submission: Submission {
-            source_code: body.source_code.clone(),
-            language: body.language.clone(),
-            user_id: body.user_id,
-            contest_id: body.contest_id,
-            problem_id: body.problem_id,
-        },
-        state: "Finished".to_string(),
-        result,
-        score,
-        cases: res.0,
-        score_vec: res.1 .1.clone(),
-    })
-    // TODO: User management
-    // TODO: Competition function
+    Ok(HttpResponse::Ok().json(response))
 }
 
 #[get("/jobs")]
-async fn get_jobs(info: web::Query<Info>) -> impl Responder {
-    let lock = RESPONSE_LIST.lock().unwrap();
-    let response_list = lock.clone();
-    let mut filtered = vec![true; response_list.len()];
-    drop(lock);
+async fn get_jobs(
+    info: web::Query<Info>,
+    page: web::Query<Page>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let filter = Filter::parse(&info)?;
+    let omit: Vec<&str> = match page.omit.as_ref() {
+        Some(omit) => omit.split(',').collect(),
+        None => Vec::new(),
+    };
+    for i in omit.iter() {
+        if *i != "source_code" && *i != "cases" {
+            return Err(Error::InvalidArgument(format!(
+                "Field '{}' can't be omitted.",
+                i
+            )));
+        }
+    }
+    let descending = match page.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid order '{}'.",
+                order
+            )))
+        }
+    };
 
-    filter(&info, &response_list, &mut filtered);
+    // Jobs hidden by a frozen ranklist are left out before sorting, so no filter reveals them
+    let access = auth::access(&req);
+    let lock = RESPONSE_LIST.lock().unwrap();
+    let mut res: Vec<&Response> = lock
+        .iter()
+        .filter(|x| {
+            filter.matches(x)
+                && (access.is_owner_or_admin(x.submission.user_id) || !crate::contests::hidden(x))
+        })
+        .collect();
 
-    let mut res: Vec<Response> = Vec::new();
-    for (i, j) in filtered.iter().enumerate() {
-        if *j {
-            res.push(response_list[i].clone());
+    // All times are generated in the same RFC 3339 format in UTC, so they compare as strings
+    match page.sort_by.as_deref() {
+        None | Some("created_time") => res.sort_by(|a, b| a.created_time.cmp(&b.created_time)),
+        Some("updated_time") => res.sort_by(|a, b| a.updated_time.cmp(&b.updated_time)),
+        Some("score") => res.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap()),
+        Some("id") => res.sort_by_key(|x| x.id),
+        Some(sort_by) => {
+            drop(lock);
+            return Err(Error::InvalidArgument(format!(
+                "Can't sort by '{}'.",
+                sort_by
+            )));
         }
     }
+    if descending {
+        res.reverse();
+    }
 
-    res.sort_by(|a, b| {
-        DateTime::parse_from_rfc3339(&a.created_time)
-            .unwrap()
-            .cmp(&DateTime::parse_from_rfc3339(&b.created_time).unwrap())
-    });
-
-    HttpResponse::Ok().json(res)
+    let total = res.len();
+    let omit_source_code = omit.contains(&"source_code");
+    let omit_cases = omit.contains(&"cases");
+    let res: Vec<Listed> = res
+        .into_iter()
+        .skip(page.offset.unwrap_or(0))
+        .take(page.limit.unwrap_or(usize::MAX))
+        .map(|x| {
+            Listed::new(
+                x,
+                !omit_source_code && access.can_read_source(x.submission.user_id),
+                !omit_cases,
+            )
+        })
+        .collect();
+    let res = HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .json(res);
+    drop(lock);
+    Ok(res)
 }
 
 #[get("/jobs/{jobid}")]
-async fn get_jobid(jobid: web::Path<u32>) -> impl Responder {
-    let jobid = jobid.clone();
+async fn get_jobid(jobid: web::Path<u32>, req: HttpRequest) -> ApiResult<HttpResponse> {
+    let jobid = *jobid;
     let lock = JOB_ID.lock().unwrap();
-    let max_id = lock.clone();
+    let max_id = *lock;
     drop(lock);
     if jobid >= max_id {
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: format!("Job {} not found", jobid).to_string(),
-        });
+        return Err(Error::NotFound(format!("Job {} not found", jobid)));
     }
+    let access = auth::access(&req);
     let lock = RESPONSE_LIST.lock().unwrap();
-    let res = lock[jobid as usize].clone();
+    let job = &lock[jobid as usize];
+    let mut listed = Listed::new(job, access.can_read_source(job.submission.user_id), true);
+    if !access.is_owner_or_admin(job.submission.user_id) && crate::contests::hidden(job) {
+        listed = listed.hide_results();
+    }
+    let res = HttpResponse::Ok().json(listed);
     drop(lock);
-    HttpResponse::Ok().json(res)
+    Ok(res)
 }
 
 #[put("/jobs/{jobid}")]
-async fn put_jobid(jobid: web::Path<u32>, config: web::Data<Config>) -> impl Responder {
-    let jobid = jobid.clone();
+async fn put_jobid(jobid: web::Path<u32>, config: web::Data<Config>) -> ApiResult<HttpResponse> {
+    let jobid = *jobid;
     let lock = JOB_ID.lock().unwrap();
-    let max_id = lock.clone();
+    let max_id = *lock;
     drop(lock);
     if jobid >= max_id {
-        return HttpResponse::NotFound().json(Error {
-            reason: "ERR_NOT_FOUND".to_string(),
-            code: 3,
-            message: format!("Job {} not found.", jobid).to_string(),
-        });
+        return Err(Error::NotFound(format!("Job {} not found.", jobid)));
     }
 
-    let lock = RESPONSE_LIST.lock().unwrap();
-    let response = lock[jobid as usize].clone();
+    let mut lock = RESPONSE_LIST.lock().unwrap();
+    let job = &mut lock[jobid as usize];
+    if job.state != "Finished" && job.state != "Canceled" {
+        drop(lock);
+        return Err(Error::InvalidState(format!("Job {} not finished.", jobid)));
+    }
+
+    requeue(job, &config);
+    let res = job.clone();
     drop(lock);
+    Ok(HttpResponse::Ok().json(res))
+}
 
-    if response.state.as_str() != "Finished" {
-        return HttpResponse::BadRequest().json(Error {
-            reason: "ERR_INVALID_STATE".to_string(),
-            code: 2,
-            message: format!("Job {} not finished.", jobid),
-        });
+/// Rejudge every finished or canceled job matching the filter
+#[post("/jobs/rejudge")]
+async fn rejudge_jobs(info: web::Json<Info>, config: web::Data<Config>) -> ApiResult<HttpResponse> {
+    let filter = Filter::parse(&info)?;
+    let mut lock = RESPONSE_LIST.lock().unwrap();
+    let mut job_ids: Vec<u32> = Vec::new();
+    let mut previous: Vec<(Result, f32)> = Vec::new();
+    for job in lock.iter_mut() {
+        if (job.state == "Finished" || job.state == "Canceled") && filter.matches(job) {
+            previous.push((job.result, job.score));
+            requeue(job, &config);
+            job_ids.push(job.id);
+        }
     }
+    drop(lock);
 
-    let res = crate::judger::judger(
-        &response.submission.source_code,
-        response.submission.problem_id as usize,
-        &response.submission.language,
-        &config,
-    );
-    let mut result: Result;
-    if res.0[0].result == Result::CompilationError {
-        result = Result::CompilationError;
-    } else {
-        result = Result::Accepted;
-        for i in res.0.iter().skip(1) {
-            if i.result != Result::Accepted {
-                result = i.result;
-                break;
-            }
+    let mut lock = REJUDGE_LIST.lock().unwrap();
+    let rejudge = Rejudge {
+        id: lock.len() as u32,
+        created_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        job_ids,
+        previous,
+    };
+    lock.push(rejudge.clone());
+    drop(lock);
+
+    Ok(HttpResponse::Ok().json(rejudge))
+}
+
+/// Compare the results of a bulk rejudge with the results before it
+#[get("/jobs/rejudge/{rejudge_id}")]
+async fn get_rejudge(rejudge_id: web::Path<u32>) -> ApiResult<HttpResponse> {
+    let lock = REJUDGE_LIST.lock().unwrap();
+    let rejudge = match lock.get(*rejudge_id as usize) {
+        Some(rejudge) => rejudge.clone(),
+        None => {
+            drop(lock);
+            return Err(Error::NotFound(format!(
+                "Rejudge {} not found.",
+                rejudge_id
+            )));
+        }
+    };
+    drop(lock);
+
+    let mut finished = 0;
+    let mut changed: Vec<Change> = Vec::new();
+    let lock = RESPONSE_LIST.lock().unwrap();
+    for (id, previous) in rejudge.job_ids.iter().zip(rejudge.previous.iter()) {
+        let job = &lock[*id as usize];
+        if job.state != "Finished" {
+            continue;
+        }
+        finished += 1;
+        if job.result != previous.0 || job.score != previous.1 {
+            changed.push(Change {
+                id: *id,
+                previous_result: previous.0,
+                previous_score: previous.1,
+                result: job.result,
+                score: job.score,
+            });
+        }
+    }
+    drop(lock);
+
+    Ok(HttpResponse::Ok().json(RejudgeSummary {
+        id: rejudge.id,
+        created_time: rejudge.created_time,
+        job_ids: rejudge.job_ids,
+        finished,
+        changed,
+    }))
+}
+
+#[get("/jobs/{jobid}/history")]
+async fn get_job_history(jobid: web::Path<u32>, req: HttpRequest) -> ApiResult<HttpResponse> {
+    let lock = RESPONSE_LIST.lock().unwrap();
+    match lock.get(*jobid as usize) {
+        Some(job)
+            if !auth::access(&req).is_owner_or_admin(job.submission.user_id)
+                && crate::contests::hidden(job) =>
+        {
+            Err(Error::Forbidden(format!(
+                "Results of job {} are frozen.",
+                jobid
+            )))
         }
+        Some(job) => Ok(HttpResponse::Ok().json(&job.history)),
+        None => Err(Error::NotFound(format!("Job {} not found.", jobid))),
     }
+}
 
-    let updated_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
-    let mut lock = RESPONSE_LIST.lock().unwrap();
-    lock[jobid as usize] = Response {
-        id: response.id,
-        created_time: response.created_time.clone(),
-        updated_time: updated_time.clone(),
-        submission: response.submission.clone(),
-        state: "Finished".to_string(),
-        result,
-        score: res.1 .0,
-        cases: res.0.clone(),
-        score_vec: res.1 .1.clone(),
+/// Stream the job as server-sent events until it is finished or canceled
+#[get("/jobs/{jobid}/events")]
+async fn get_job_events(jobid: web::Path<u32>, req: HttpRequest) -> ApiResult<HttpResponse> {
+    let lock = RESPONSE_LIST.lock().unwrap();
+    let job = match lock.get(*jobid as usize) {
+        Some(job) => job,
+        None => return Err(Error::NotFound(format!("Job {} not found.", jobid))),
     };
+    // Events carry the source code
+    let access = auth::access(&req);
+    if !access.can_read_source(job.submission.user_id) {
+        return Err(Error::Forbidden(format!("Job {} isn't yours.", jobid)));
+    }
+    let live = access.is_owner_or_admin(job.submission.user_id);
+    let receiver = events::subscribe(Topic::Job(job.id), Some(job), live);
+    drop(lock);
+    Ok(events::response(receiver))
+}
+
+/// Remove a queueing job from the queue, or kill a running one
+#[post("/jobs/{jobid}/cancel")]
+async fn cancel_jobid(jobid: web::Path<u32>, req: HttpRequest) -> ApiResult<HttpResponse> {
+    let jobid = *jobid;
+    let mut lock = RESPONSE_LIST.lock().unwrap();
+    if jobid as usize >= lock.len() {
+        drop(lock);
+        return Err(Error::NotFound(format!("Job {} not found.", jobid)));
+    }
+    // Only the owner or an admin can cancel
+    auth::acting_user(&req, Some(lock[jobid as usize].submission.user_id))?;
+
+    let job = &mut lock[jobid as usize];
+    match job.state.as_str() {
+        "Queueing" => {
+            let mut queue = JOB_QUEUE.0.lock().unwrap();
+            queue.retain(|x| *x != jobid);
+        }
+        // The judging thread drops the results of a canceled job
+        "Running" => crate::judger::CANCEL.store(true, Ordering::SeqCst),
+        _ => {
+            drop(lock);
+            return Err(Error::InvalidState(format!(
+                "Job {} is neither queueing nor running.",
+                jobid
+            )));
+        }
+    }
+    job.updated_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    job.attempt += 1;
+    job.state = "Canceled".to_string();
+    job.result = Result::Canceled;
+    changed(job);
+    let res = job.clone();
     drop(lock);
-    HttpResponse::Ok().json(Response {
-        id: response.id,
-        created_time: response.created_time,
-        updated_time,
-        submission: response.submission,
-        state: "Finished".to_string(),
-        result,
-        score: res.1 .0,
-        cases: res.0,
-        score_vec: res.1 .1.clone(),
-    })
-}
-
-fn filter(info: &web::Query<Info>, response_list: &Vec<Response>, filtered: &mut Vec<bool>) {
-    // TODO
-    if let Some(user_id) = info.user_id.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            if j.submission.user_id != *user_id {
-                filtered[i] = false;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Judge queueing jobs one by one, run in its own thread
+pub fn judge_loop(config: web::Data<Config>) {
+    loop {
+        let (queue, condvar) = &**JOB_QUEUE;
+        let mut lock = queue.lock().unwrap();
+        while lock.is_empty() {
+            lock = condvar.wait(lock).unwrap();
+        }
+        let id = lock.pop_front().unwrap();
+        drop(lock);
+
+        crate::judger::CANCEL.store(false, Ordering::SeqCst);
+        let mut lock = RESPONSE_LIST.lock().unwrap();
+        let job = &mut lock[id as usize];
+        if job.state != "Queueing" {
+            continue;
+        }
+        job.state = "Running".to_string();
+        job.result = Result::Running;
+        job.updated_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        changed(job);
+        let submission = job.submission.clone();
+        let attempt = job.attempt;
+        drop(lock);
+
+        // Show the cases judged so far, and the one being judged
+        let progress = |cases: &[Case]| {
+            let mut lock = RESPONSE_LIST.lock().unwrap();
+            let job = &mut lock[id as usize];
+            if job.attempt != attempt {
+                return;
             }
+            job.cases[..cases.len()].clone_from_slice(cases);
+            if let Some(case) = job.cases.get_mut(cases.len()) {
+                case.result = Result::Running;
+            }
+            job.updated_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            changed(job);
+        };
+
+        let problem_id = config
+            .problems
+            .iter()
+            .position(|x| x.id == submission.problem_id)
+            .unwrap();
+        let version = config.problems[problem_id].version();
+        fs::remove_dir_all("./TMPDIR").ok();
+        // Failures to judge come back as errors; the catch is only a last
+        // resort so that a bug in the judger doesn't stop the queue
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::judger::judger(
+                &submission.source_code,
+                problem_id,
+                &submission.language,
+                &config,
+                &progress,
+            )
+        }));
+
+        let mut lock = RESPONSE_LIST.lock().unwrap();
+        let job = &mut lock[id as usize];
+        // Canceled, and maybe requeued, while it was judged
+        if job.attempt != attempt {
+            continue;
         }
+        job.updated_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        job.state = "Finished".to_string();
+        match res {
+            Ok(Ok((cases, (score, score_vec, subtask_scores)))) => {
+                job.result = overall_result(&cases);
+                job.score = score;
+                job.score_vec = score_vec;
+                job.subtask_scores = subtask_scores;
+                job.cases = cases;
+            }
+            // The compiler or the work directory failed, not the submission
+            Ok(Err(err)) => {
+                log::error!("Can't judge job {}: {}", id, err);
+                job.result = Result::SystemError;
+            }
+            Err(_) => job.result = Result::SystemError,
+        }
+        let attempt = Attempt {
+            time: job.updated_time.clone(),
+            version,
+            result: job.result,
+            score: job.score,
+            cases: job.cases.clone(),
+        };
+        job.history.push(attempt);
+        changed(job);
+        crate::webhooks::job_finished(job, &config);
     }
-    if let Some(user_name) = info.user_name.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            let lock = USER_LIST.lock().unwrap();
-            if lock[j.submission.user_id as usize].name != *user_name {
-                filtered[i] = false;
+}
+
+/// Reset a job to waiting and put it back into the queue
+fn requeue(job: &mut Response, config: &Config) {
+    let problem_cases = config
+        .problems
+        .iter()
+        .find(|x| x.id == job.submission.problem_id)
+        .unwrap()
+        .cases
+        .len();
+    job.updated_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    job.attempt += 1;
+    job.state = "Queueing".to_string();
+    job.result = Result::Waiting;
+    job.score = 0.0;
+    job.score_vec = Vec::new();
+    job.subtask_scores = Vec::new();
+    job.cases = waiting_cases(problem_cases);
+    changed(job);
+    enqueue(job.id);
+}
+
+/// Tell streams and caches that a job changed.
+/// Call while holding `RESPONSE_LIST`.
+fn changed(job: &Response) {
+    events::publish(job);
+    crate::stats::invalidate(job.submission.user_id);
+}
+
+fn enqueue(id: u32) {
+    let (queue, condvar) = &**JOB_QUEUE;
+    queue.lock().unwrap().push_back(id);
+    condvar.notify_one();
+}
+
+/// Cases of a job before judging, case 0 is the compilation
+fn waiting_cases(count: usize) -> Vec<Case> {
+    (0..=count as u32)
+        .map(|id| Case {
+            id,
+            result: Result::Waiting,
+            time: 0,
+            memory: 0,
+            info: "".to_string(),
+            sample: None,
+        })
+        .collect()
+}
+
+fn overall_result(cases: &[Case]) -> Result {
+    if cases[0].result == Result::CompilationError {
+        return Result::CompilationError;
+    }
+    for i in cases.iter().skip(1) {
+        if i.result != Result::Accepted {
+            return i.result;
+        }
+    }
+    Result::Accepted
+}
+
+impl Filter {
+    /// Check every field of `info`, resolving user names to user ids.
+    /// Must not be called while holding `RESPONSE_LIST`.
+    fn parse(info: &Info) -> ApiResult<Filter> {
+        fn number<T: std::str::FromStr>(
+            field: &str,
+            value: &Option<String>,
+        ) -> ApiResult<Option<T>> {
+            match value {
+                Some(value) => match value.trim().parse() {
+                    Ok(x) => Ok(Some(x)),
+                    Err(_) => Err(invalid_argument(field, value)),
+                },
+                None => Ok(None),
+            }
+        }
+        fn time(field: &str, value: &Option<String>) -> ApiResult<Option<DateTime<FixedOffset>>> {
+            match value {
+                Some(value) => match DateTime::parse_from_rfc3339(value) {
+                    Ok(x) => Ok(Some(x)),
+                    Err(_) => Err(invalid_argument(field, value)),
+                },
+                None => Ok(None),
             }
+        }
+
+        let mut filter = Filter {
+            contest_id: number("contest_id", &info.contest_id)?,
+            problem_id: number("problem_id", &info.problem_id)?,
+            from: time("from", &info.from)?,
+            to: time("to", &info.to)?,
+            score_min: number("score_min", &info.score_min)?,
+            score_max: number("score_max", &info.score_max)?,
+            id_from: number("id_from", &info.id_from)?,
+            id_to: number("id_to", &info.id_to)?,
+            ..Default::default()
+        };
+        if let Some(user_id) = number::<u32>("user_id", &info.user_id)? {
+            filter.user_ids = Some(vec![user_id]);
+        }
+        if let Some(user_name) = info.user_name.as_ref() {
+            let lock = USER_LIST.lock().unwrap();
+            let ids: Vec<u32> = lock
+                .iter()
+                .filter(|x| x.name == *user_name)
+                .filter_map(|x| x.id)
+                .collect();
             drop(lock);
+            filter.user_ids = Some(match filter.user_ids {
+                Some(user_ids) => ids.into_iter().filter(|x| user_ids.contains(x)).collect(),
+                None => ids,
+            });
         }
-    }
-    if let Some(contest_id) = info.contest_id.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            if j.submission.contest_id != *contest_id {
-                filtered[i] = false;
+        if let Some(language) = info.language.as_ref() {
+            filter.languages = Some(language.split(',').map(|x| x.to_string()).collect());
+        }
+        if let Some(state) = info.state.as_ref() {
+            if !["Queueing", "Running", "Finished", "Canceled"].contains(&state.as_str()) {
+                return Err(invalid_argument("state", state));
             }
+            filter.state = Some(state.clone());
         }
-    }
-    if let Some(problem_id) = info.problem_id.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            if j.submission.problem_id != *problem_id {
-                filtered[i] = false;
+        if let Some(result) = info.result.as_ref() {
+            let mut results = Vec::new();
+            for i in result.split(',') {
+                match serde_json::from_value(serde_json::Value::String(i.to_string())) {
+                    Ok(x) => results.push(x),
+                    Err(_) => return Err(invalid_argument("result", i)),
+                }
             }
+            filter.results = Some(results);
         }
+        Ok(filter)
     }
-    if let Some(language) = info.language.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            if j.submission.language != *language {
-                filtered[i] = false;
+
+    fn matches(&self, job: &Response) -> bool {
+        fn within<T: PartialOrd>(value: T, min: &Option<T>, max: &Option<T>) -> bool {
+            if let Some(min) = min.as_ref() {
+                if value < *min {
+                    return false;
+                }
+            }
+            if let Some(max) = max.as_ref() {
+                if value > *max {
+                    return false;
+                }
             }
+            true
         }
-    }
-    if let Some(from) = info.from.as_ref() {
-        let t = DateTime::parse_from_rfc3339(from).unwrap();
-        for (i, j) in response_list.iter().enumerate() {
-            if DateTime::parse_from_rfc3339(&j.created_time).unwrap() < t {
-                filtered[i] = false;
+
+        if let Some(user_ids) = self.user_ids.as_ref() {
+            if !user_ids.contains(&job.submission.user_id) {
+                return false;
             }
         }
-    }
-    if let Some(to) = info.to.as_ref() {
-        let t = DateTime::parse_from_rfc3339(to).unwrap();
-        for (i, j) in response_list.iter().enumerate() {
-            if DateTime::parse_from_rfc3339(&j.created_time).unwrap() > t {
-                filtered[i] = false;
+        if let Some(contest_id) = self.contest_id {
+            if job.submission.contest_id != contest_id {
+                return false;
             }
         }
-    }
-    if let Some(state) = info.state.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            if j.state != *state {
-                filtered[i] = false;
+        if let Some(problem_id) = self.problem_id {
+            if job.submission.problem_id != problem_id {
+                return false;
             }
         }
-    }
-    if let Some(result) = info.result.as_ref() {
-        for (i, j) in response_list.iter().enumerate() {
-            if j.result != *result {
-                filtered[i] = false;
+        if let Some(languages) = self.languages.as_ref() {
+            if !languages.contains(&job.submission.language) {
+                return false;
+            }
+        }
+        if self.from.is_some() || self.to.is_some() {
+            // Created times are generated by the server, so they always parse
+            let created_time = DateTime::parse_from_rfc3339(&job.created_time).unwrap();
+            if !within(created_time, &self.from, &self.to) {
+                return false;
             }
         }
+        if let Some(state) = self.state.as_ref() {
+            if job.state != *state {
+                return false;
+            }
+        }
+        if let Some(results) = self.results.as_ref() {
+            if !results.contains(&job.result) {
+                return false;
+            }
+        }
+        within(job.score, &self.score_min, &self.score_max)
+            && within(job.id, &self.id_from, &self.id_to)
     }
 }
+
+fn invalid_argument(field: &str, value: &str) -> Error {
+    Error::InvalidArgument(format!("Invalid {} '{}'.", field, value))
+}
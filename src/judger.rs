@@ -1,34 +1,37 @@
 use actix_web::web;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::process::Stdio;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use std::{fs, io::Write, process::Command};
-use wait_timeout::ChildExt;
 
-use crate::config::Config;
-use crate::jobs::{Case, Result};
+use crate::config::{self, Config};
+use crate::jobs::{Case, Difference, Result, SampleOutput};
+
+/// Max bytes of program output returned in a response
+const OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// Set to stop the job being judged
+pub static CANCEL: AtomicBool = AtomicBool::new(false);
+
+/// Total score, scores of accepted cases, and scores of each subtask.
+/// Each case is a subtask of its own unless the problem packs them.
+pub type Scores = (f32, Vec<f32>, Vec<f32>);
 
 pub fn judger(
-    source_code: &String,
+    source_code: &str,
     problem_id: usize,
-    language: &String,
+    language: &str,
     config: &web::Data<Config>,
-) -> (Vec<Case>, (f32, Vec<f32>)) {
+    progress: &dyn Fn(&[Case]),
+) -> io::Result<(Vec<Case>, Scores)> {
     // Create temporart direction
-    fs::create_dir("./TMPDIR").unwrap();
-    let mut main_file: fs::File;
-    match language.as_str() {
-        "Rust" => main_file = fs::File::create("./TMPDIR/main.rs").unwrap(),
-        "C" => main_file = fs::File::create("./TMPDIR/main.c").unwrap(),
-        "C++" => main_file = fs::File::create("./TMPDIR/main.cpp").unwrap(),
-        _ => unreachable!(),
-    };
-    main_file.write_all(source_code.as_bytes()).unwrap();
-
-    // Compile
-    let (compile_success, compile_time) = compile(config, language);
+    fs::create_dir("./TMPDIR")?;
+    let (compile_success, compile_time) = prepare("./TMPDIR", source_code, language, config)?;
 
     let mut cases: Vec<Case> = Vec::new();
     if compile_success {
@@ -38,6 +41,7 @@ pub fn judger(
             time: compile_time,
             memory: 0,
             info: "".to_string(),
+            sample: None,
         });
     } else {
         // Compilation Error
@@ -48,33 +52,213 @@ pub fn judger(
                 time: compile_time,
                 memory: 0,
                 info: "".to_string(),
+                sample: None,
             })
         }
     }
 
-    let score = get_scores(config, &mut cases, problem_id);
+    let score = get_scores(config, &mut cases, problem_id, progress);
+
+    fs::remove_dir_all("./TMPDIR")?;
+    Ok((cases, score))
+}
+
+/// Output of a custom test run
+pub struct RunOutput {
+    /// None when time limit exceeded
+    pub status: Option<ExitStatus>,
+    pub time: u128,
+    /// Peak resident memory in bytes
+    pub memory: u32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run the source code on the given input under the time limit,
+/// used for custom test runs which are not stored as jobs
+/// return whether success and compile time, and the output if compiled or the compiler messages,
+/// or the error of starting the compiler
+pub fn run(
+    source_code: &str,
+    language: &str,
+    input: &str,
+    time_limit: u64,
+    dir: &str,
+    config: &web::Data<Config>,
+) -> io::Result<((bool, u128), std::result::Result<RunOutput, String>)> {
+    fs::create_dir_all(dir).unwrap();
+    let compile_res = match prepare(dir, source_code, language, config) {
+        Ok(res) => res,
+        Err(err) => {
+            fs::remove_dir_all(dir).unwrap();
+            return Err(err);
+        }
+    };
+    if !compile_res.0 {
+        let log = read_output(&format!("{}/compile.log", dir));
+        fs::remove_dir_all(dir).unwrap();
+        return Ok((compile_res, Err(log)));
+    }
+
+    fs::write(format!("{}/in", dir), input).unwrap();
+    let in_file = fs::File::open(format!("{}/in", dir)).unwrap();
+    let out_file = fs::File::create(format!("{}/out", dir)).unwrap();
+    let err_file = fs::File::create(format!("{}/err", dir)).unwrap();
+    let (status, time, memory) = execute(
+        dir,
+        Stdio::from(in_file),
+        Stdio::from(out_file),
+        Stdio::from(err_file),
+        time_limit,
+        &AtomicBool::new(false),
+    );
+    let stdout = read_output(&format!("{}/out", dir));
+    let stderr = read_output(&format!("{}/err", dir));
+
+    fs::remove_dir_all(dir).unwrap();
+    Ok((
+        compile_res,
+        Ok(RunOutput {
+            status,
+            time,
+            memory,
+            stdout,
+            stderr,
+        }),
+    ))
+}
 
-    fs::remove_dir_all("./TMPDIR").unwrap();
-    return (cases, score);
+/// Write the source code into `dir` and compile it to `dir/main`,
+/// reusing the executable of an identical submission.
+/// Messages of a failed compilation are left in `dir/compile.log`.
+/// return whether success and compile time
+fn prepare(
+    dir: &str,
+    source_code: &str,
+    language: &str,
+    config: &web::Data<Config>,
+) -> io::Result<(bool, u128)> {
+    let mut main_file = match language {
+        "Rust" => fs::File::create(format!("{}/main.rs", dir)).unwrap(),
+        "C" => fs::File::create(format!("{}/main.c", dir)).unwrap(),
+        "C++" => fs::File::create(format!("{}/main.cpp", dir)).unwrap(),
+        _ => unreachable!(),
+    };
+    main_file.write_all(source_code.as_bytes()).unwrap();
+
+    let language_config = config
+        .languages
+        .iter()
+        .find(|x| x.name == language)
+        .unwrap();
+    let key = crate::cache::key(language_config, source_code);
+    let output = format!("{}/main", dir);
+    let log = format!("{}/compile.log", dir);
+    match crate::cache::lookup(&key, &output, &log) {
+        Some(res) => Ok(res),
+        None => {
+            let res = compile(config, language, dir)?;
+            crate::cache::insert(&key, &output, &log, res, &config.cache);
+            Ok(res)
+        }
+    }
+}
+
+/// Run `dir/main` with the given stdio, killing it when time limit exceeded or canceled
+/// return the exit status (None when killed), run time and peak memory in bytes
+fn execute(
+    dir: &str,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+    time_limit: u64,
+    cancel: &AtomicBool,
+) -> (Option<ExitStatus>, u128, u32) {
+    let run_start = Instant::now();
+    // Reaped by `wait_with_usage` instead of `wait`
+    #[allow(clippy::zombie_processes)]
+    let mut child = Command::new(format!("{}/main", dir))
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn()
+        .unwrap();
+    // Reap the process on another thread, which also gets its resource usage
+    let pid = child.id() as libc::pid_t;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(wait_with_usage(pid)).ok());
+
+    let time_limit = Duration::from_micros(time_limit);
+    let mut exited = None;
+    // Wait in short slices to notice cancellation
+    while !cancel.load(Ordering::SeqCst) {
+        let elapsed = run_start.elapsed();
+        if elapsed >= time_limit {
+            break;
+        }
+        match receiver.recv_timeout((time_limit - elapsed).min(Duration::from_millis(10))) {
+            Ok(res) => {
+                exited = Some(res);
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    let run_time = run_start.elapsed().as_micros();
+    match exited {
+        Some((status, memory)) => (Some(status), run_time, memory),
+        None => {
+            child.kill().ok();
+            let memory = receiver.recv().map_or(0, |(_, memory)| memory);
+            (None, run_time, memory)
+        }
+    }
+}
+
+/// Wait for the process to end
+/// return its exit status and peak resident memory in bytes
+fn wait_with_usage(pid: libc::pid_t) -> (ExitStatus, u32) {
+    let mut status = 0;
+    // SAFETY: rusage is plain data, and both pointers outlive the call
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    while unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } < 0 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            break;
+        }
+    }
+    // Linux reports the peak resident set size in KiB
+    let memory = (usage.ru_maxrss as u64).saturating_mul(1024);
+    (
+        ExitStatus::from_raw(status),
+        memory.min(u32::MAX as u64) as u32,
+    )
+}
+
+/// Read a program output, truncated to `OUTPUT_LIMIT` bytes
+fn read_output(path: &str) -> String {
+    let mut output = fs::read(path).unwrap();
+    output.truncate(OUTPUT_LIMIT);
+    String::from_utf8_lossy(&output).to_string()
 }
 
 /// Compile according to language
 /// return whether success and compile time
-fn compile(config: &web::Data<Config>, language: &String) -> (bool, u128) {
+fn compile(config: &web::Data<Config>, language: &str, dir: &str) -> io::Result<(bool, u128)> {
     // Add arguments
     let mut args: Vec<String> = Vec::new();
     for j in config.languages.iter().enumerate() {
         if j.1.name == *language {
             for i in config.languages[j.0].command.iter().skip(1) {
                 if i == "%OUTPUT%" {
-                    args.push("./TMPDIR/main".to_string());
+                    args.push(format!("{}/main", dir));
                     continue;
                 }
                 if i == "%INPUT%" {
-                    match language.as_str() {
-                        "Rust" => args.push("./TMPDIR/main.rs".to_string()),
-                        "C" => args.push("./TMPDIR/main.c".to_string()),
-                        "C++" => args.push("./TMPDIR/main.cpp".to_string()),
+                    match language {
+                        "Rust" => args.push(format!("{}/main.rs", dir)),
+                        "C" => args.push(format!("{}/main.c", dir)),
+                        "C++" => args.push(format!("{}/main.cpp", dir)),
                         _ => unreachable!(),
                     };
                     continue;
@@ -84,33 +268,29 @@ fn compile(config: &web::Data<Config>, language: &String) -> (bool, u128) {
         }
     }
 
-    // Compile
+    // Compile, keeping the messages of the compiler
+    let log = fs::File::create(format!("{}/compile.log", dir)).unwrap();
     let compile_start = Instant::now();
-    let compile_status: std::process::ExitStatus;
-    match language.as_str() {
-        "Rust" => {
-            compile_status = Command::new("rustc").args(args).status().unwrap();
-        }
-        "C" => {
-            compile_status = Command::new("gcc").args(args).status().unwrap();
-        }
-        "C++" => {
-            compile_status = Command::new("g++").args(args).status().unwrap();
-        }
+    let compile_status = match language {
+        "Rust" => Command::new("rustc").args(args).stderr(log).status()?,
+        "C" => Command::new("gcc").args(args).stderr(log).status()?,
+        "C++" => Command::new("g++").args(args).stderr(log).status()?,
         _ => unreachable!(),
-    }
+    };
 
     let compile_time = compile_start.elapsed().as_micros();
-    (compile_status.success(), compile_time)
+    Ok((compile_status.success(), compile_time))
 }
 
 fn get_scores(
     config: &web::Data<Config>,
     cases: &mut Vec<Case>,
     problem_id: usize,
-) -> (f32, Vec<f32>) {
+    progress: &dyn Fn(&[Case]),
+) -> Scores {
     let mut total_score = 0.0;
     let mut score_vec: Vec<f32> = Vec::new();
+    let mut case_scores = vec![0.0; config.problems[problem_id].cases.len()];
     let mut id: u32 = 0;
 
     let mut packing: Vec<Vec<bool>> = Vec::new();
@@ -126,8 +306,13 @@ fn get_scores(
         }
     }
     let mut pack_score = vec![0 as f32; packing.len()];
+    let problem_type = config.problems[problem_id].r#type.as_str();
 
     for i in config.problems[problem_id].cases.iter() {
+        progress(cases);
+        if CANCEL.load(Ordering::SeqCst) {
+            break;
+        }
         id += 1;
 
         // The case in which pack
@@ -157,6 +342,7 @@ fn get_scores(
                         time: 0,
                         memory: 0,
                         info: "".to_string(),
+                        sample: None,
                     });
                     skipped = true;
                     break;
@@ -175,6 +361,7 @@ fn get_scores(
                 time: 0,
                 memory: 0,
                 info: "".to_string(),
+                sample: None,
             });
             continue;
         }
@@ -183,15 +370,15 @@ fn get_scores(
         let out_file = fs::File::create("./TMPDIR/out").unwrap();
 
         // Run the executable file
-        let run_start = Instant::now();
-        let mut child = Command::new("./TMPDIR/main")
-            .stdin(Stdio::from(in_file))
-            .stdout(out_file)
-            .stderr(Stdio::null())
-            .spawn()
-            .unwrap();
-        let time_limit = Duration::from_micros(i.time_limit);
-        let run_status = match child.wait_timeout(time_limit).unwrap() {
+        let (run_status, run_time, run_memory) = execute(
+            "./TMPDIR",
+            Stdio::from(in_file),
+            Stdio::from(out_file),
+            Stdio::null(),
+            i.time_limit,
+            &CANCEL,
+        );
+        let run_status = match run_status {
             Some(status) => status,
             None => {
                 cases.push(Case {
@@ -200,25 +387,26 @@ fn get_scores(
                     time: 0,
                     memory: 0,
                     info: "".to_string(),
+                    sample: sample_output(i, problem_type),
                 });
                 continue;
             }
         };
-        let run_time = run_start.elapsed().as_micros();
 
         if !run_status.success() {
             cases.push(Case {
                 id,
                 result: Result::RuntimeError,
                 time: run_time,
-                memory: 0,
+                memory: run_memory,
                 info: "".to_string(),
+                sample: sample_output(i, problem_type),
             });
             continue;
         }
 
         let mut info = String::new();
-        if match config.problems[problem_id].r#type.as_str() {
+        if match problem_type {
             "standard" | "dynamic_ranking" => {
                 standart_compare(&i.answer_file, &"./TMPDIR/out".to_string())
             }
@@ -239,15 +427,19 @@ fn get_scores(
                 id,
                 result: Result::Accepted,
                 time: run_time,
-                memory: 0,
+                memory: run_memory,
                 info,
+                sample: sample_output(i, problem_type),
             });
-            score_vec.push(i.score);
+            // Sample cases are not scored
+            let score = if i.sample { 0.0 } else { i.score };
+            score_vec.push(score);
+            case_scores[id as usize - 1] = score;
             if packing.is_empty() {
-                total_score += i.score;
+                total_score += score;
             } else {
                 packing[packing_id][packing_index as usize] = true;
-                pack_score[packing_id] += i.score;
+                pack_score[packing_id] += score;
                 if packing_index == packing[packing_id].len() as u32 - 1 {
                     total_score += pack_score[packing_id];
                 }
@@ -257,13 +449,75 @@ fn get_scores(
                 id,
                 result: Result::WrongAnswer,
                 time: run_time,
-                memory: 0,
+                memory: run_memory,
                 info,
+                sample: sample_output(i, problem_type),
             })
         }
         fs::remove_file("./TMPDIR/out").unwrap();
     }
-    return (total_score, score_vec);
+    // A pack scores only when all of its cases are accepted
+    let subtask_scores = if packing.is_empty() {
+        case_scores
+    } else {
+        packing
+            .iter()
+            .zip(pack_score)
+            .map(|(pack, score)| if pack.iter().all(|x| *x) { score } else { 0.0 })
+            .collect()
+    };
+    (total_score, score_vec, subtask_scores)
+}
+
+/// Expected and actual output of a sample case, hidden cases return None
+fn sample_output(case: &config::Case, problem_type: &str) -> Option<SampleOutput> {
+    if !case.sample {
+        return None;
+    }
+    let expected_output = read_output(&case.answer_file);
+    let actual_output = read_output("./TMPDIR/out");
+    let first_difference = match problem_type {
+        "standard" | "dynamic_ranking" => first_difference(
+            expected_output.lines().map(|x| x.trim_end()),
+            actual_output.lines().map(|x| x.trim_end()),
+        ),
+        "strict" => first_difference(expected_output.split('\n'), actual_output.split('\n')),
+        // The special judge decides what a difference is
+        _ => None,
+    };
+    Some(SampleOutput {
+        expected_output,
+        actual_output,
+        first_difference,
+    })
+}
+
+/// Find the first differing line, and the first differing column in it
+fn first_difference<'a>(
+    expected: impl Iterator<Item = &'a str>,
+    actual: impl Iterator<Item = &'a str>,
+) -> Option<Difference> {
+    let expected: Vec<&str> = expected.collect();
+    let actual: Vec<&str> = actual.collect();
+    for line in 0..expected.len().max(actual.len()) {
+        let (a, b) = match (expected.get(line), actual.get(line)) {
+            (Some(a), Some(b)) => (*a, *b),
+            _ => {
+                return Some(Difference {
+                    line: line + 1,
+                    column: 1,
+                })
+            }
+        };
+        if a != b {
+            let same = a.chars().zip(b.chars()).take_while(|x| x.0 == x.1).count();
+            return Some(Difference {
+                line: line + 1,
+                column: same + 1,
+            });
+        }
+    }
+    None
 }
 
 fn standart_compare(answer_path: &String, out_path: &String) -> bool {
@@ -295,7 +549,7 @@ fn strict_compare(answer_path: &String, out_path: &String) -> bool {
 fn special_compare(
     info: &mut String,
     special_judge: Vec<String>,
-    answer_path: &String,
+    answer_path: &str,
     out_path: &String,
 ) -> bool {
     let args: Vec<String> = special_judge
@@ -305,7 +559,7 @@ fn special_compare(
             if x == "%OUTPUT%" {
                 out_path.to_string()
             } else if x == "%ANSWER%" {
-                answer_path.clone()
+                answer_path.to_string()
             } else {
                 x.clone()
             }
@@ -326,12 +580,8 @@ fn special_compare(
     *info = tmp[1].clone();
     fs::remove_file("./TMPDIR/special").unwrap();
     match tmp[0].as_str() {
-        "Accepted" => {
-            return true;
-        }
-        "Wrong Answer" => {
-            return false;
-        }
+        "Accepted" => true,
+        "Wrong Answer" => false,
         _ => unreachable!(),
     }
 }
@@ -1,17 +1,37 @@
+mod auth;
+mod cache;
+mod clarifications;
 mod config;
 mod contests;
+mod error;
+mod events;
 mod jobs;
 mod judger;
+mod problems;
+mod runs;
+mod stats;
+mod teams;
 mod users;
+mod virtuals;
+mod webhooks;
 
 use actix_web::{get, middleware::Logger, post, web, App, HttpServer, Responder};
-use contests::{get_contests, get_contests_by_id, get_contests_ranklist, post_contest};
-use env_logger;
-use jobs::{get_jobid, get_jobs};
-use jobs::{post_jobs, put_jobid};
-use log;
+use auth::{login, logout};
+use clarifications::{answer_clarification, get_clarification, get_clarification_events};
+use clarifications::{get_clarifications, post_clarification};
+use contests::{approve_participant, get_contests_ranklist, kick_participant};
+use contests::{end_contest, extend_contest, pause_contest, resume_contest};
+use contests::{get_contest_events, get_contests, get_contests_by_id};
+use contests::{post_contest, register_contest, unfreeze_contest};
+use jobs::{cancel_jobid, get_job_events, get_job_history, get_jobid, get_jobs, get_rejudge};
+use jobs::{post_jobs, put_jobid, rejudge_jobs};
+use problems::{get_problem, get_problem_cases, get_problems};
+use runs::post_runs;
+use stats::get_user_stats;
 use structopt::StructOpt;
-use users::{get_user, post_user};
+use teams::{get_team, get_teams, post_team};
+use users::{delete_user, get_user, get_user_by_id, import_users, post_user};
+use virtuals::{get_virtual_runs, start_virtual};
 
 #[derive(StructOpt)]
 struct Opt {
@@ -34,29 +54,80 @@ async fn greet(name: web::Path<String>) -> impl Responder {
 async fn exit() -> impl Responder {
     log::info!("Shutdown as requested");
     std::process::exit(0);
-    format!("Exited")
+    "Exited".to_string()
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let opt = Opt::from_args();
     let config = config::Config::parse_from_file(&opt.config);
+    if let Some(password) = config.auth.root_password.as_ref() {
+        users::USER_LIST.lock().unwrap()[0].password = Some(auth::hash_password(password));
+    }
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    if !config.auth.required {
+        log::warn!(
+            "auth.required is off: requests without a token act as admin and roles are not enforced"
+        );
+    }
+
+    let judge_config = web::Data::new(config.clone());
+    std::thread::spawn(move || jobs::judge_loop(judge_config));
+    let webhook_config = web::Data::new(config.clone());
+    std::thread::spawn(move || webhooks::webhook_loop(webhook_config));
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(config.clone()))
+            .app_data(web::JsonConfig::default().error_handler(error::json_error))
+            .app_data(web::QueryConfig::default().error_handler(error::query_error))
+            .app_data(web::PathConfig::default().error_handler(error::path_error))
+            .wrap_fn(auth::authorize)
             .wrap(Logger::default())
             .route("/hello", web::get().to(|| async { "Hello World!" }))
             .service(greet)
+            .service(rejudge_jobs)
             .service(post_jobs)
             .service(get_jobs)
             .service(get_jobid)
+            .service(get_job_history)
+            .service(get_job_events)
+            .service(get_rejudge)
             .service(put_jobid)
+            .service(cancel_jobid)
+            .service(post_runs)
+            .service(get_problems)
+            .service(get_problem)
+            .service(get_problem_cases)
             .service(post_user)
+            .service(login)
+            .service(logout)
+            .service(import_users)
             .service(get_user)
+            .service(get_user_by_id)
+            .service(get_user_stats)
+            .service(post_team)
+            .service(get_teams)
+            .service(get_team)
+            .service(delete_user)
             .service(get_contests_ranklist)
+            .service(get_contest_events)
+            .service(post_clarification)
+            .service(answer_clarification)
+            .service(get_clarifications)
+            .service(get_clarification_events)
+            .service(get_clarification)
+            .service(register_contest)
+            .service(approve_participant)
+            .service(kick_participant)
+            .service(unfreeze_contest)
+            .service(extend_contest)
+            .service(pause_contest)
+            .service(resume_contest)
+            .service(end_contest)
+            .service(start_virtual)
+            .service(get_virtual_runs)
             .service(post_contest)
             .service(get_contests)
             .service(get_contests_by_id)
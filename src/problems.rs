@@ -0,0 +1,116 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::fs;
+
+use crate::auth;
+use crate::config::{Config, Problem};
+use crate::error::{ApiResult, Error};
+
+/// A problem without its test data
+#[derive(Serialize)]
+struct ProblemInfo<'a> {
+    id: u32,
+    name: &'a str,
+    r#type: &'a str,
+    full_score: f32,
+    cases: usize,
+    /// Names of the languages jobs may use, any language when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_languages: Option<&'a Vec<String>>,
+}
+
+impl<'a> ProblemInfo<'a> {
+    fn new(problem: &'a Problem) -> Self {
+        ProblemInfo {
+            id: problem.id,
+            name: &problem.name,
+            r#type: &problem.r#type,
+            full_score: problem.full_score(),
+            cases: problem.cases.len(),
+            allowed_languages: problem.allowed_languages.as_ref(),
+        }
+    }
+}
+
+/// Test data of a case, hidden cases leave out input and answer for non-setters
+#[derive(Serialize)]
+struct CaseData {
+    id: usize,
+    sample: bool,
+    score: f32,
+    time_limit: u64,
+    memory_limit: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<String>,
+}
+
+fn read(path: &str) -> ApiResult<String> {
+    match fs::read(path) {
+        Ok(data) => Ok(String::from_utf8_lossy(&data).to_string()),
+        Err(_) => Err(Error::Internal(format!("Can't read {}.", path))),
+    }
+}
+
+#[get("/problems")]
+async fn get_problems(config: web::Data<Config>) -> ApiResult<HttpResponse> {
+    let res: Vec<ProblemInfo> = config.problems.iter().map(ProblemInfo::new).collect();
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/problems/{problem_id}")]
+async fn get_problem(
+    problem_id: web::Path<u32>,
+    config: web::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    match config.problems.iter().find(|x| x.id == *problem_id) {
+        Some(problem) => Ok(HttpResponse::Ok().json(ProblemInfo::new(problem))),
+        None => Err(Error::NotFound(format!(
+            "Problem {} not found.",
+            problem_id
+        ))),
+    }
+}
+
+/// Cases of a problem, with the test data the user may read
+#[get("/problems/{problem_id}/cases")]
+async fn get_problem_cases(
+    problem_id: web::Path<u32>,
+    req: HttpRequest,
+    config: web::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    let problem = match config.problems.iter().find(|x| x.id == *problem_id) {
+        Some(problem) => problem,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Problem {} not found.",
+                problem_id
+            )))
+        }
+    };
+    let hidden = auth::access(&req).can_read_hidden_data();
+
+    let mut res: Vec<CaseData> = Vec::new();
+    for (i, case) in problem.cases.iter().enumerate() {
+        let visible = case.sample || hidden;
+        res.push(CaseData {
+            id: i + 1,
+            sample: case.sample,
+            score: case.score,
+            time_limit: case.time_limit,
+            memory_limit: case.memory_limit,
+            input: if visible {
+                Some(read(&case.input_file)?)
+            } else {
+                None
+            },
+            answer: if visible {
+                Some(read(&case.answer_file)?)
+            } else {
+                None
+            },
+        });
+    }
+    Ok(HttpResponse::Ok().json(res))
+}
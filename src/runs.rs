@@ -0,0 +1,179 @@
+use actix_web::{post, web, HttpResponse};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::config::Config;
+use crate::error::{ApiResult, Error};
+use crate::jobs::Result;
+
+/// Time limit in microseconds when no problem is given
+const DEFAULT_TIME_LIMIT: u64 = 1_000_000;
+
+/// Max runs compiling or running at the same time, later ones wait
+const MAX_RUNNING: usize = 4;
+
+#[derive(Deserialize)]
+struct PostRun {
+    source_code: String,
+    language: String,
+    /// Use the limits of this problem
+    problem_id: Option<u32>,
+    input: String,
+    /// Compared with stdout in standard mode when given
+    answer: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RunResponse {
+    result: Result,
+    stdout: String,
+    /// Messages of the compiler on compilation errors
+    stderr: String,
+    time: u128,
+    /// Peak resident memory in bytes
+    memory: u32,
+}
+
+lazy_static! {
+    static ref RUN_ID: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
+    /// Number of runs compiling or running
+    static ref RUNNING: Arc<(Mutex<usize>, Condvar)> = Arc::new((Mutex::new(0), Condvar::new()));
+}
+
+/// One of the `MAX_RUNNING` places to run in, given back when dropped
+struct Slot;
+
+impl Slot {
+    /// Block until a place is free
+    fn take() -> Slot {
+        let (lock, condvar) = &**RUNNING;
+        let mut running = condvar
+            .wait_while(lock.lock().unwrap(), |running| *running >= MAX_RUNNING)
+            .unwrap();
+        *running += 1;
+        Slot
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let (lock, condvar) = &**RUNNING;
+        *lock.lock().unwrap() -= 1;
+        condvar.notify_one();
+    }
+}
+
+/// Compile and run a submission on custom input.
+/// Runs are not stored, so they never count towards submission limits or ranklists.
+/// `Accepted` means the program exited normally (and matched `answer` when given).
+#[post("/runs")]
+async fn post_runs(body: web::Json<PostRun>, config: web::Data<Config>) -> ApiResult<HttpResponse> {
+    if !config.languages.iter().any(|x| x.name == body.language) {
+        return Err(Error::NotFound(format!(
+            "Language {} not found.",
+            body.language
+        )));
+    }
+
+    let mut time_limit = DEFAULT_TIME_LIMIT;
+    if let Some(problem_id) = body.problem_id {
+        match config.problems.iter().find(|x| x.id == problem_id) {
+            Some(problem) => {
+                time_limit = problem
+                    .cases
+                    .iter()
+                    .map(|x| x.time_limit)
+                    .max()
+                    .unwrap_or(DEFAULT_TIME_LIMIT);
+            }
+            None => {
+                return Err(Error::NotFound(format!(
+                    "Problem {} not found.",
+                    problem_id
+                )));
+            }
+        }
+    }
+
+    // The guard ends with the block, so it is never held across the run below
+    let id = {
+        let mut lock = RUN_ID.lock().unwrap();
+        *lock += 1;
+        *lock - 1
+    };
+
+    // Every run gets its own directory, so it can't disturb a running job.
+    // Compiling and running block, so they happen off the async workers,
+    // and only a few at a time.
+    let dir = format!("./RUNDIR/{}", id);
+    let body = body.into_inner();
+    let answer = body.answer.clone();
+    let language = body.language.clone();
+    let run = {
+        let dir = dir.clone();
+        web::block(move || {
+            let _slot = Slot::take();
+            crate::judger::run(
+                &body.source_code,
+                &body.language,
+                &body.input,
+                time_limit,
+                &dir,
+                &config,
+            )
+        })
+    };
+    let (compile_res, run_res) = match run.await {
+        Ok(Ok(res)) => res,
+        Ok(Err(err)) => {
+            return Err(Error::External(format!(
+                "Can't start the compiler of {}: {}",
+                language, err
+            )))
+        }
+        Err(_) => {
+            fs::remove_dir_all(&dir).ok();
+            return Err(Error::Internal(format!("Run {} failed.", id)));
+        }
+    };
+
+    let output = match run_res {
+        Ok(res) => res,
+        Err(log) => {
+            return Ok(HttpResponse::Ok().json(RunResponse {
+                result: Result::CompilationError,
+                stdout: String::new(),
+                stderr: log,
+                time: compile_res.1,
+                memory: 0,
+            }))
+        }
+    };
+
+    let result = match output.status {
+        None => Result::TimeLimitExceeded,
+        Some(status) if !status.success() => Result::RuntimeError,
+        Some(_) => match answer.as_ref() {
+            Some(answer)
+                if answer
+                    .lines()
+                    .map(|x| x.trim_end())
+                    .ne(output.stdout.lines().map(|x| x.trim_end())) =>
+            {
+                Result::WrongAnswer
+            }
+            _ => Result::Accepted,
+        },
+    };
+
+    Ok(HttpResponse::Ok().json(RunResponse {
+        result,
+        stdout: output.stdout,
+        stderr: output.stderr,
+        // Until killed when time limit exceeded
+        time: output.time,
+        memory: output.memory,
+    }))
+}
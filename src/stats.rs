@@ -0,0 +1,124 @@
+use actix_web::{get, web, HttpResponse};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{ApiResult, Error};
+use crate::jobs::{Response, Result, RESPONSE_LIST};
+use crate::users::USER_LIST;
+
+/// Progress of a user, counting finished jobs only
+#[derive(Serialize, Clone)]
+struct Stats {
+    user_id: u32,
+    submissions: u32,
+    /// Problems with an accepted job
+    solved: Vec<u32>,
+    /// Problems with finished jobs but none accepted
+    attempted: Vec<u32>,
+    /// Jobs by result
+    results: BTreeMap<String, u32>,
+    /// Jobs by language
+    languages: BTreeMap<String, u32>,
+    /// Highest score by problem
+    best_scores: BTreeMap<u32, f32>,
+    /// Jobs by day of creation, oldest first
+    activity: Vec<Day>,
+}
+
+#[derive(Serialize, Clone)]
+struct Day {
+    date: String,
+    submissions: u32,
+}
+
+lazy_static! {
+    /// Stats by user id, dropped whenever a job of the user changes
+    static ref STATS_CACHE: Arc<Mutex<HashMap<u32, Stats>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Forget the cached stats of a user.
+/// Call while holding `RESPONSE_LIST`, so stats computed meanwhile aren't cached.
+pub fn invalidate(user_id: u32) {
+    STATS_CACHE.lock().unwrap().remove(&user_id);
+}
+
+fn compute(user_id: u32, jobs: &[Response]) -> Stats {
+    let mut stats = Stats {
+        user_id,
+        submissions: 0,
+        solved: Vec::new(),
+        attempted: Vec::new(),
+        results: BTreeMap::new(),
+        languages: BTreeMap::new(),
+        best_scores: BTreeMap::new(),
+        activity: Vec::new(),
+    };
+    let mut solved = BTreeSet::new();
+    let mut activity: BTreeMap<&str, u32> = BTreeMap::new();
+    for job in jobs
+        .iter()
+        .filter(|x| x.submission.user_id == user_id && x.state == "Finished")
+    {
+        let problem_id = job.submission.problem_id;
+        stats.submissions += 1;
+        if job.result == Result::Accepted {
+            solved.insert(problem_id);
+        }
+        let result = serde_json::to_value(job.result).unwrap();
+        *stats
+            .results
+            .entry(result.as_str().unwrap().to_string())
+            .or_insert(0) += 1;
+        *stats
+            .languages
+            .entry(job.submission.language.clone())
+            .or_insert(0) += 1;
+        let best = stats.best_scores.entry(problem_id).or_insert(job.score);
+        if job.score > *best {
+            *best = job.score;
+        }
+        // Times are rfc3339 in UTC, so the day is the first ten characters
+        *activity.entry(&job.created_time[..10]).or_insert(0) += 1;
+    }
+    stats.attempted = stats
+        .best_scores
+        .keys()
+        .filter(|x| !solved.contains(x))
+        .copied()
+        .collect();
+    stats.solved = solved.into_iter().collect();
+    stats.activity = activity
+        .into_iter()
+        .map(|(date, submissions)| Day {
+            date: date.to_string(),
+            submissions,
+        })
+        .collect();
+    stats
+}
+
+#[get("/users/{id}/stats")]
+async fn get_user_stats(id: web::Path<u32>) -> ApiResult<HttpResponse> {
+    let user_id = *id;
+    if !USER_LIST
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|x| x.id == Some(user_id))
+    {
+        return Err(Error::NotFound(format!("User {} not found.", user_id)));
+    }
+
+    let cached = STATS_CACHE.lock().unwrap().get(&user_id).cloned();
+    if let Some(stats) = cached {
+        return Ok(HttpResponse::Ok().json(stats));
+    }
+    let lock = RESPONSE_LIST.lock().unwrap();
+    let stats = compute(user_id, &lock);
+    STATS_CACHE.lock().unwrap().insert(user_id, stats.clone());
+    drop(lock);
+    Ok(HttpResponse::Ok().json(stats))
+}
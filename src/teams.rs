@@ -0,0 +1,185 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::auth;
+use crate::contests::{check_teams, State, CONTEST_LIST};
+use crate::error::{ApiResult, Error};
+use crate::users::USER_LIST;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Team {
+    pub id: u32,
+    pub name: String,
+    pub member_ids: Vec<u32>,
+    /// Users a member added, who join by adding themselves too
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invited_ids: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct PostTeam {
+    id: Option<u32>,
+    name: String,
+    member_ids: Vec<u32>,
+}
+
+lazy_static! {
+    pub static ref TEAM_LIST: Arc<Mutex<Vec<Team>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// Create a team, or update the team with `id`.
+/// Only members, invited users and admins may do it.
+/// Other users a member lists are invited rather than added, and join by posting the team with themselves,
+/// which changes nothing else of the team.
+/// Members of a team in a contest that hasn't ended are fixed, except by admins.
+#[post("/teams")]
+async fn post_team(body: web::Json<PostTeam>, req: HttpRequest) -> ApiResult<HttpResponse> {
+    let mut member_ids = body.member_ids.clone();
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    if member_ids.is_empty() {
+        return Err(Error::InvalidArgument("A team needs members.".to_string()));
+    }
+    let lock = USER_LIST.lock().unwrap();
+    if let Some(id) = member_ids
+        .iter()
+        .find(|x| !lock.iter().any(|y| y.id == Some(**x)))
+    {
+        let id = *id;
+        drop(lock);
+        return Err(Error::NotFound(format!("User {} not found.", id)));
+    }
+    drop(lock);
+    let admin = auth::access(&req).is_signed_admin();
+    let user_id = if admin {
+        None
+    } else {
+        Some(auth::acting_user(&req, None)?)
+    };
+
+    // Contests are locked first, like when they check their teams
+    let contest_list = CONTEST_LIST.lock().unwrap();
+    let mut lock = TEAM_LIST.lock().unwrap();
+    if lock
+        .iter()
+        .any(|x| x.name == body.name && Some(x.id) != body.id)
+    {
+        return Err(Error::InvalidArgument(format!(
+            "Team name '{}' already exists.",
+            body.name
+        )));
+    }
+    let previous = match body.id {
+        Some(id) => match lock.get(id as usize) {
+            Some(team) => Some(team.clone()),
+            None => return Err(Error::NotFound(format!("Team {} not found.", id))),
+        },
+        None => None,
+    };
+
+    let mut team = Team {
+        id: body.id.unwrap_or(lock.len() as u32),
+        name: body.name.clone(),
+        member_ids,
+        invited_ids: Vec::new(),
+    };
+    if let Some(user_id) = user_id {
+        let allowed = match previous.as_ref() {
+            Some(x) => x.member_ids.contains(&user_id) || x.invited_ids.contains(&user_id),
+            None => team.member_ids.contains(&user_id),
+        };
+        if !allowed {
+            return Err(Error::Forbidden(format!(
+                "User {} is not in the team.",
+                user_id
+            )));
+        }
+        match previous.as_ref() {
+            // Invited users only accept or decline
+            Some(x) if !x.member_ids.contains(&user_id) => {
+                let accepted = team.member_ids.contains(&user_id);
+                team.name = x.name.clone();
+                team.member_ids = x.member_ids.clone();
+                team.invited_ids = x.invited_ids.clone();
+                team.invited_ids.retain(|x| *x != user_id);
+                if accepted {
+                    team.member_ids.push(user_id);
+                    team.member_ids.sort_unstable();
+                }
+            }
+            previous => {
+                let members: &[u32] = match previous {
+                    Some(x) => &x.member_ids,
+                    None => &[],
+                };
+                let (joined, invited) = team
+                    .member_ids
+                    .iter()
+                    .partition(|x| **x == user_id || members.contains(x));
+                team.member_ids = joined;
+                team.invited_ids = invited;
+            }
+        }
+        if team.member_ids.is_empty() {
+            return Err(Error::InvalidArgument("A team needs members.".to_string()));
+        }
+    }
+
+    if let Some(previous) = previous.as_ref() {
+        if previous.member_ids != team.member_ids {
+            let now = Utc::now();
+            let mut teams = lock.clone();
+            teams[team.id as usize] = team.clone();
+            for contest in contest_list
+                .iter()
+                .filter(|x| x.team_ids.contains(&(team.id as usize)))
+            {
+                if !admin && contest.state(now) != State::Ended {
+                    return Err(Error::InvalidState(format!(
+                        "Team {} is in contest {}, which hasn't ended.",
+                        team.id, contest.id
+                    )));
+                }
+                check_teams(&contest.team_ids, &teams)?;
+            }
+        }
+    }
+
+    match body.id {
+        Some(id) => lock[id as usize] = team.clone(),
+        None => lock.push(team.clone()),
+    }
+    drop(lock);
+    drop(contest_list);
+    Ok(HttpResponse::Ok().json(team))
+}
+
+#[get("/teams")]
+async fn get_teams() -> ApiResult<HttpResponse> {
+    let lock = TEAM_LIST.lock().unwrap();
+    let res = lock.clone();
+    drop(lock);
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/teams/{team_id}")]
+async fn get_team(team_id: web::Path<u32>) -> ApiResult<HttpResponse> {
+    let lock = TEAM_LIST.lock().unwrap();
+    match lock.get(*team_id as usize) {
+        Some(team) => Ok(HttpResponse::Ok().json(team)),
+        None => Err(Error::NotFound(format!("Team {} not found.", team_id))),
+    }
+}
+
+/// The team among `team_ids` that the user is a member of
+pub fn team_of(team_ids: &[usize], user_id: u32) -> Option<u32> {
+    let lock = TEAM_LIST.lock().unwrap();
+    team_ids
+        .iter()
+        .filter_map(|x| lock.get(*x))
+        .find(|x| x.member_ids.contains(&user_id))
+        .map(|x| x.id)
+}
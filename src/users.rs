@@ -1,30 +1,170 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 
+use crate::auth::{self, Access};
+use crate::config::Config;
+use crate::error::{ApiResult, Error};
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct User {
     pub id: Option<u32>,
     pub name: String,
+    /// Argon2 hash, users without one can't log in
+    #[serde(skip)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// School or organization
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub created_time: String,
+    /// Deleted users keep their jobs but can't log in or submit
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+/// What everyone reads about a user, in listings and ranklists
+#[derive(Serialize, Clone)]
+pub struct PublicUser {
+    pub id: u32,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+}
+
+impl From<&User> for PublicUser {
+    fn from(user: &User) -> Self {
+        PublicUser {
+            id: user.id.unwrap(),
+            name: user.name.clone(),
+            display_name: user.display_name.clone(),
+            organization: user.organization.clone(),
+        }
+    }
+}
+
+/// A user as read by a request, in full only by the user itself and admins
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Profile<'a> {
+    Full(&'a User),
+    Public(PublicUser),
+}
+
+impl<'a> Profile<'a> {
+    fn new(user: &'a User, access: Access) -> Self {
+        if access.is_owner_or_admin(user.id.unwrap()) {
+            Profile::Full(user)
+        } else {
+            Profile::Public(user.into())
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    /// Problem setter, may read hidden test data
+    Setter,
+    #[default]
+    Contestant,
+    /// May read everything but not submit
+    Observer,
+}
+
+#[derive(Deserialize)]
+struct PostUser {
+    id: Option<u32>,
+    name: String,
+    password: Option<String>,
+    /// Only admins may set roles
+    role: Option<Role>,
+    display_name: Option<String>,
+    email: Option<String>,
+    organization: Option<String>,
+    /// Only admins may disable or restore users
+    disabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Search {
+    /// Beginning of the user name
+    prefix: Option<String>,
+    #[serde(default)]
+    include_disabled: bool,
 }
 
-#[derive(Deserialize, Serialize)]
-struct Error {
-    reason: String,
-    code: u8,
-    message: String,
+/// A line of an imported roster
+#[derive(Deserialize)]
+struct Row {
+    name: String,
+    password: Option<String>,
+    role: Option<Role>,
+    display_name: Option<String>,
+    email: Option<String>,
+    organization: Option<String>,
 }
 
 lazy_static! {
     pub static ref USER_LIST: Arc<Mutex<Vec<User>>> = Arc::new(Mutex::new(vec![User {
         id: Some(0),
-        name: "root".to_string()
+        name: "root".to_string(),
+        password: None,
+        role: Role::Admin,
+        display_name: None,
+        email: None,
+        organization: None,
+        created_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        disabled: false,
     }]));
 }
 
+/// Check that a user exists and may still submit
+pub fn check_active(user_id: u32) -> ApiResult<()> {
+    let lock = USER_LIST.lock().unwrap();
+    match lock.iter().find(|x| x.id == Some(user_id)) {
+        Some(user) if user.disabled => Err(Error::InvalidState(format!(
+            "User {} is disabled.",
+            user_id
+        ))),
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound(format!("User {} not found.", user_id))),
+    }
+}
+
 #[post("/users")]
-async fn post_user(user: web::Json<User>) -> impl Responder {
+async fn post_user(
+    user: web::Json<PostUser>,
+    req: HttpRequest,
+    config: web::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    if user.role.is_some() && !auth::access(&req).is_signed_admin() {
+        return Err(Error::Forbidden("Only admins may set roles.".to_string()));
+    }
+    if user.disabled.is_some() && !auth::access(&req).is_signed_admin() {
+        return Err(Error::Forbidden(
+            "Only admins may disable users.".to_string(),
+        ));
+    }
+    if let Some(id) = user.id {
+        // Only the user or an admin can rename it
+        auth::acting_user(&req, Some(id))?;
+    } else if config.auth.required && user.password.is_none() {
+        return Err(Error::InvalidArgument("Missing password.".to_string()));
+    }
+    let password = user.password.as_deref().map(auth::hash_password);
+
     let mut lock = USER_LIST.lock().unwrap();
     if let Some(id) = user.id {
         if let Ok(user_self) = lock.binary_search_by_key(&id, |x| x.id.unwrap()) {
@@ -34,54 +174,189 @@ async fn post_user(user: web::Json<User>) -> impl Responder {
                 }
                 if j.name == user.name {
                     drop(lock);
-                    return HttpResponse::BadRequest().json(Error {
-                        reason: "ERR_INVALID_ARGUMENT".to_string(),
-                        code: 1,
-                        message: format!("User name '{}' already exists.", user.name).to_string(),
-                    });
+                    return Err(Error::InvalidArgument(format!(
+                        "User name '{}' already exists.",
+                        user.name
+                    )));
                 }
             }
             lock[user_self].name = user.name.clone();
+            if password.is_some() {
+                lock[user_self].password = password;
+            }
+            if let Some(role) = user.role {
+                lock[user_self].role = role;
+            }
+            if let Some(disabled) = user.disabled {
+                if id == 0 && disabled {
+                    drop(lock);
+                    return Err(Error::InvalidArgument(
+                        "Root can't be disabled.".to_string(),
+                    ));
+                }
+                lock[user_self].disabled = disabled;
+            }
+            let profile = &mut lock[user_self];
+            for (field, value) in [
+                (&mut profile.display_name, &user.display_name),
+                (&mut profile.email, &user.email),
+                (&mut profile.organization, &user.organization),
+            ] {
+                if value.is_some() {
+                    *field = value.clone();
+                }
+            }
+            let res = lock[user_self].clone();
             drop(lock);
-            return HttpResponse::Ok().json(User {
-                id: Some(id),
-                name: user.name.clone(),
-            });
+            Ok(HttpResponse::Ok().json(res))
         } else {
             drop(lock);
-            return HttpResponse::NotFound().json(Error {
-                reason: "ERR_NOT_FOUND".to_string(),
-                code: 3,
-                message: format!("User {} not found.", id).to_string(),
-            });
+            Err(Error::NotFound(format!("User {} not found.", id)))
         }
     } else {
         for i in lock.iter() {
             if i.name == user.name {
                 drop(lock);
-                return HttpResponse::BadRequest().json(Error {
-                    reason: "ERR_INVALID_ARGUMENT".to_string(),
-                    code: 1,
-                    message: format!("User name '{}' already exists.", user.name).to_string(),
-                });
+                return Err(Error::InvalidArgument(format!(
+                    "User name '{}' already exists.",
+                    user.name
+                )));
             }
         }
-        let id = Some(lock.last().unwrap().id.unwrap() + 1);
-        lock.push(User {
-            id,
+        let user = User {
+            id: Some(lock.last().unwrap().id.unwrap() + 1),
             name: user.name.clone(),
-        });
+            password,
+            role: user.role.unwrap_or_default(),
+            display_name: user.display_name.clone(),
+            email: user.email.clone(),
+            organization: user.organization.clone(),
+            created_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            disabled: user.disabled.unwrap_or(false),
+        };
+        lock.push(user.clone());
         drop(lock);
-        return HttpResponse::Ok().json(User {
-            id,
-            name: user.name.clone(),
-        });
+        Ok(HttpResponse::Ok().json(user))
     }
 }
 
+/// Add every user of a csv roster, or none of them if a line is invalid.
+/// The header names the columns, of which only `name` is required.
+#[post("/users/import")]
+async fn import_users(body: String, config: web::Data<Config>) -> ApiResult<HttpResponse> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let mut rows: Vec<Row> = Vec::new();
+    for (i, row) in reader.deserialize().enumerate() {
+        // Line 1 is the header
+        let line = i + 2;
+        let row: Row =
+            row.map_err(|err| Error::InvalidArgument(format!("Line {}: {}", line, err)))?;
+        if row.name.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "Line {}: Missing name.",
+                line
+            )));
+        }
+        if config.auth.required && row.password.is_none() {
+            return Err(Error::InvalidArgument(format!(
+                "Line {}: Missing password.",
+                line
+            )));
+        }
+        if rows.iter().any(|x| x.name == row.name) {
+            return Err(Error::InvalidArgument(format!(
+                "Line {}: User name '{}' is repeated.",
+                line, row.name
+            )));
+        }
+        rows.push(row);
+    }
+    let passwords: Vec<Option<String>> = rows
+        .iter()
+        .map(|x| x.password.as_deref().map(auth::hash_password))
+        .collect();
+
+    let mut lock = USER_LIST.lock().unwrap();
+    if let Some(user) = lock.iter().find(|x| rows.iter().any(|y| y.name == x.name)) {
+        let name = user.name.clone();
+        drop(lock);
+        return Err(Error::InvalidArgument(format!(
+            "User name '{}' already exists.",
+            name
+        )));
+    }
+    let created_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let mut res = Vec::new();
+    for (row, password) in rows.into_iter().zip(passwords) {
+        let user = User {
+            id: Some(lock.last().unwrap().id.unwrap() + 1),
+            name: row.name,
+            password,
+            role: row.role.unwrap_or_default(),
+            display_name: row.display_name,
+            email: row.email,
+            organization: row.organization,
+            created_time: created_time.clone(),
+            disabled: false,
+        };
+        lock.push(user.clone());
+        res.push(user);
+    }
+    drop(lock);
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// List users, optionally only those whose name starts with `prefix`
 #[get("/users")]
-async fn get_user() -> impl Responder {
+async fn get_user(search: web::Query<Search>, req: HttpRequest) -> ApiResult<HttpResponse> {
+    let access = auth::access(&req);
+    let lock = USER_LIST.lock().unwrap();
+    let res: Vec<Profile> = lock
+        .iter()
+        .filter(|x| search.include_disabled || !x.disabled)
+        .filter(|x| match search.prefix.as_ref() {
+            Some(prefix) => x.name.starts_with(prefix.as_str()),
+            None => true,
+        })
+        .map(|x| Profile::new(x, access))
+        .collect();
+    let res = HttpResponse::Ok().json(res);
+    drop(lock);
+    Ok(res)
+}
+
+#[get("/users/{id}")]
+async fn get_user_by_id(id: web::Path<u32>, req: HttpRequest) -> ApiResult<HttpResponse> {
     let lock = USER_LIST.lock().unwrap();
-    let res = lock.clone();
-    HttpResponse::Ok().json(res)
+    match lock.iter().find(|x| x.id == Some(*id)) {
+        Some(user) => Ok(HttpResponse::Ok().json(Profile::new(user, auth::access(&req)))),
+        None => Err(Error::NotFound(format!("User {} not found.", id))),
+    }
+}
+
+/// Disable a user, keeping its jobs and ranking
+#[delete("/users/{id}")]
+async fn delete_user(id: web::Path<u32>) -> ApiResult<HttpResponse> {
+    if *id == 0 {
+        return Err(Error::InvalidArgument(
+            "Root can't be disabled.".to_string(),
+        ));
+    }
+    let mut lock = USER_LIST.lock().unwrap();
+    let user = match lock.iter_mut().find(|x| x.id == Some(*id)) {
+        Some(user) => user,
+        None => return Err(Error::NotFound(format!("User {} not found.", id))),
+    };
+    if user.disabled {
+        return Err(Error::InvalidState(format!(
+            "User {} is already disabled.",
+            id
+        )));
+    }
+    user.disabled = true;
+    let res = user.clone();
+    drop(lock);
+    Ok(HttpResponse::Ok().json(res))
 }
@@ -0,0 +1,129 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::auth;
+use crate::contests::{format_time, parse_time, State, CONTEST_LIST};
+use crate::error::{ApiResult, Error};
+
+/// A personal run of a past contest by a user, lasting as long as the contest did
+#[derive(Serialize, Clone)]
+pub struct VirtualRun {
+    pub id: u32,
+    pub contest_id: u32,
+    pub user_id: u32,
+    pub from: String,
+    pub to: String,
+}
+
+impl VirtualRun {
+    pub fn start(&self) -> DateTime<Utc> {
+        parse_time(&self.from)
+    }
+}
+
+#[derive(Deserialize)]
+struct StartVirtual {
+    /// Taken from the token when logged in
+    user_id: Option<u32>,
+}
+
+lazy_static! {
+    static ref VIRTUAL_LIST: Arc<Mutex<Vec<VirtualRun>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// The run of the user in the contest if it is going on
+pub fn active_run(contest_id: usize, user_id: u32, now: DateTime<Utc>) -> Option<u32> {
+    let lock = VIRTUAL_LIST.lock().unwrap();
+    lock.iter()
+        .find(|x| {
+            x.contest_id as usize == contest_id
+                && x.user_id == user_id
+                && now >= parse_time(&x.from)
+                && now <= parse_time(&x.to)
+        })
+        .map(|x| x.id)
+}
+
+/// Every run of a contest, oldest first
+pub fn runs_of(contest_id: usize) -> Vec<VirtualRun> {
+    let lock = VIRTUAL_LIST.lock().unwrap();
+    lock.iter()
+        .filter(|x| x.contest_id as usize == contest_id)
+        .cloned()
+        .collect()
+}
+
+/// Start a virtual run of an ended contest from now.
+/// Each user runs a contest once, and only if they didn't take part in it.
+#[post("/contests/{contest_id}/virtual")]
+async fn start_virtual(
+    contest_id: web::Path<usize>,
+    body: web::Json<StartVirtual>,
+    req: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    let user_id = auth::acting_user(&req, body.user_id)?;
+    crate::users::check_active(user_id)?;
+    let now = Utc::now();
+
+    let contest = match CONTEST_LIST.lock().unwrap().get(*contest_id) {
+        Some(contest) if contest.id != 0 => contest.clone(),
+        _ => {
+            return Err(Error::NotFound(format!(
+                "Contest {} not found.",
+                contest_id
+            )))
+        }
+    };
+    if contest.teams {
+        return Err(Error::InvalidState(
+            "Team contests can't be run virtually.".to_string(),
+        ));
+    }
+    if contest.state(now) != State::Ended {
+        return Err(Error::InvalidState(format!(
+            "Contest {} hasn't ended.",
+            contest_id
+        )));
+    }
+    if contest.user_ids.contains(&(user_id as usize)) {
+        return Err(Error::InvalidState(format!(
+            "User {} took part in contest {}.",
+            user_id, contest_id
+        )));
+    }
+
+    let mut lock = VIRTUAL_LIST.lock().unwrap();
+    if lock
+        .iter()
+        .any(|x| x.contest_id as usize == *contest_id && x.user_id == user_id)
+    {
+        return Err(Error::InvalidState(format!(
+            "User {} has run contest {} already.",
+            user_id, contest_id
+        )));
+    }
+    let run = VirtualRun {
+        id: lock.len() as u32,
+        contest_id: *contest_id as u32,
+        user_id,
+        from: format_time(now),
+        to: format_time(now + (contest.end() - contest.start())),
+    };
+    lock.push(run.clone());
+    drop(lock);
+    Ok(HttpResponse::Ok().json(run))
+}
+
+#[get("/contests/{contest_id}/virtual")]
+async fn get_virtual_runs(contest_id: web::Path<usize>) -> ApiResult<HttpResponse> {
+    if CONTEST_LIST.lock().unwrap().len() <= *contest_id || *contest_id == 0 {
+        return Err(Error::NotFound(format!(
+            "Contest {} not found.",
+            contest_id
+        )));
+    }
+    Ok(HttpResponse::Ok().json(runs_of(*contest_id)))
+}
@@ -0,0 +1,230 @@
+use actix_web::web;
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::contests::{State, CONTEST_LIST};
+use crate::jobs::{Response, Result};
+
+/// How often contests are checked for starting or ending
+const CONTEST_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest wait before a retry, however many times the backoff doubled
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'a str,
+    time: String,
+    contest_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<JobSummary<'a>>,
+}
+
+/// A finished job without its source code and cases
+#[derive(Serialize)]
+struct JobSummary<'a> {
+    id: u32,
+    created_time: &'a str,
+    updated_time: &'a str,
+    user_id: u32,
+    problem_id: u32,
+    language: &'a str,
+    result: Result,
+    score: f32,
+}
+
+/// A payload waiting to be sent to a webhook
+struct Delivery {
+    /// Index in `Config::webhooks`
+    webhook: usize,
+    event: String,
+    body: String,
+    retried: u32,
+    due: Instant,
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Phase {
+    Before,
+    Running,
+    Ended,
+}
+
+lazy_static! {
+    static ref DELIVERIES: Arc<(Mutex<VecDeque<Delivery>>, Condvar)> =
+        Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+}
+
+/// Notify webhooks of a finished job
+pub fn job_finished(job: &Response, config: &Config) {
+    notify(
+        "job_finished",
+        job.submission.contest_id,
+        Some(JobSummary {
+            id: job.id,
+            created_time: &job.created_time,
+            updated_time: &job.updated_time,
+            user_id: job.submission.user_id,
+            problem_id: job.submission.problem_id,
+            language: &job.submission.language,
+            result: job.result,
+            score: job.score,
+        }),
+        config,
+    );
+}
+
+fn notify(event: &str, contest_id: u32, job: Option<JobSummary>, config: &Config) {
+    let body = serde_json::to_string(&Payload {
+        event,
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        contest_id,
+        job,
+    })
+    .unwrap();
+
+    let (queue, condvar) = &**DELIVERIES;
+    let mut lock = queue.lock().unwrap();
+    for (i, webhook) in config.webhooks.iter().enumerate() {
+        if webhook.events.is_empty() || webhook.events.iter().any(|x| x == event) {
+            lock.push_back(Delivery {
+                webhook: i,
+                event: event.to_string(),
+                body: body.clone(),
+                retried: 0,
+                due: Instant::now(),
+            });
+        }
+    }
+    drop(lock);
+    condvar.notify_one();
+}
+
+/// Send queued payloads and watch contests for starting and ending, run in its own thread
+pub fn webhook_loop(config: web::Data<Config>) {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap();
+    let mut phases: HashMap<usize, Phase> = HashMap::new();
+    let mut next_check = Instant::now();
+    loop {
+        if Instant::now() >= next_check {
+            check_contests(&mut phases, &config);
+            next_check = Instant::now() + CONTEST_CHECK_INTERVAL;
+        }
+
+        let (queue, condvar) = &**DELIVERIES;
+        let lock = queue.lock().unwrap();
+        let now = Instant::now();
+        match lock.iter().position(|x| x.due <= now) {
+            Some(i) => {
+                let mut lock = lock;
+                let delivery = lock.remove(i).unwrap();
+                drop(lock);
+                deliver(&client, delivery, &config);
+            }
+            None => {
+                let wake = match lock.iter().map(|x| x.due).min() {
+                    Some(due) if due < next_check => due,
+                    _ => next_check,
+                };
+                drop(condvar.wait_timeout(lock, wake - now).unwrap());
+            }
+        }
+    }
+}
+
+/// Send a payload once, queueing it again with backoff when it fails
+fn deliver(client: &reqwest::blocking::Client, mut delivery: Delivery, config: &Config) {
+    let webhook = &config.webhooks[delivery.webhook];
+    let mut request = client
+        .post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header("X-Event", &delivery.event)
+        .body(delivery.body.clone());
+    if let Some(secret) = webhook.secret.as_ref() {
+        request = request.header(
+            "X-Signature",
+            format!("sha256={}", sign(secret, &delivery.body)),
+        );
+    }
+    let success = match request.send() {
+        Ok(res) => res.status().is_success(),
+        Err(_) => false,
+    };
+    if success {
+        return;
+    }
+
+    if delivery.retried >= webhook.retries {
+        log::warn!(
+            "Webhook {} failed {} times, dropping {}",
+            webhook.url,
+            delivery.retried + 1,
+            delivery.event
+        );
+        return;
+    }
+    delivery.due = Instant::now() + backoff(webhook.backoff, delivery.retried);
+    delivery.retried += 1;
+    DELIVERIES.0.lock().unwrap().push_back(delivery);
+}
+
+/// Wait `backoff` ms doubled `retried` times, up to `MAX_BACKOFF`
+fn backoff(backoff: u64, retried: u32) -> Duration {
+    let millis = match 2_u64.checked_pow(retried) {
+        Some(factor) => backoff.saturating_mul(factor),
+        None if backoff == 0 => 0,
+        None => u64::MAX,
+    };
+    Duration::from_millis(millis).min(MAX_BACKOFF)
+}
+
+/// Notify webhooks of contests that started or ended since the last check.
+/// Contests are first seen without notifying, so a restart doesn't repeat old events.
+fn check_contests(phases: &mut HashMap<usize, Phase>, config: &Config) {
+    let now = Utc::now();
+    let lock = CONTEST_LIST.lock().unwrap();
+    let current: Vec<(usize, Phase)> = lock
+        .iter()
+        .skip(1)
+        .map(|x| {
+            let phase = match x.state(now) {
+                State::Upcoming => Phase::Before,
+                State::Ended => Phase::Ended,
+                _ => Phase::Running,
+            };
+            (x.id, phase)
+        })
+        .collect();
+    drop(lock);
+
+    for (id, phase) in current {
+        let last = match phases.insert(id, phase) {
+            Some(last) => last,
+            None => continue,
+        };
+        if last < Phase::Running && phase >= Phase::Running {
+            notify("contest_started", id as u32, None, config);
+        }
+        if last < Phase::Ended && phase == Phase::Ended {
+            notify("contest_ended", id as u32, None, config);
+        }
+    }
+}
+
+/// HMAC-SHA256 of the body as hex
+fn sign(secret: &str, body: &str) -> String {
+    // Keys of any length are accepted
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
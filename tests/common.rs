@@ -1,9 +1,17 @@
+// Kept as shipped; allow the lints it predates
+#![allow(
+    clippy::expect_fun_call,
+    clippy::needless_borrow,
+    clippy::needless_borrows_for_generic_args,
+    clippy::single_component_path_imports
+)]
 use assert_json_diff::{assert_json_matches_no_panic, CompareMode, Config};
 use lazy_static::lazy_static;
 use reqwest::blocking::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::env::consts::EXE_EXTENSION;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
@@ -73,6 +81,12 @@ struct TestRequest {
     path: String,
     method: String,
     content: Value,
+    /// Send the token of the last login of this user name, or this text as the token
+    #[serde(default)]
+    token: Option<String>,
+    /// Send `content`, which is then a string, as is with this content type instead of as json
+    #[serde(default)]
+    content_type: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -117,6 +131,7 @@ pub struct TestCase {
     stdout_file: PathBuf,
     stderr_file: PathBuf,
     http_file: PathBuf,
+    tokens: HashMap<String, String>, // tokens from POST /login by user name
 }
 
 impl TestCase {
@@ -149,6 +164,7 @@ impl TestCase {
             stdout_file,
             stderr_file,
             http_file,
+            tokens: HashMap::new(),
         }
     }
 
@@ -287,12 +303,24 @@ impl TestCase {
         let method =
             reqwest::Method::from_bytes(&c.request.method.to_uppercase().as_bytes()).unwrap();
 
+        let token = c
+            .request
+            .token
+            .as_ref()
+            .map(|x| self.tokens.get(x).unwrap_or(x).clone());
         let check_status_and_get_body = |url: &str, method: reqwest::Method| -> Value {
             let mut request = CLIENT
                 .request(method.clone(), url)
                 .timeout(Duration::from_millis(c.timeout));
+            if let Some(token) = token.as_ref() {
+                request = request.bearer_auth(token);
+            }
             if let reqwest::Method::GET = method {
                 // no json body
+            } else if let Some(content_type) = c.request.content_type.as_ref() {
+                request = request
+                    .header("Content-Type", content_type)
+                    .body(c.request.content.as_str().unwrap().to_string());
             } else {
                 request = request.json(&c.request.content);
             }
@@ -354,6 +382,14 @@ impl TestCase {
             }
         }
 
+        if c.request.path == "login" {
+            if let (Some(name), Some(token)) =
+                (c.request.content["name"].as_str(), body["token"].as_str())
+            {
+                self.tokens.insert(name.to_string(), token.to_string());
+            }
+        }
+
         // check final result
         if let Err(error) = assert_json_matches_no_panic(
             &body,
@@ -0,0 +1,432 @@
+mod common;
+use common::TestCase;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_ext_01_custom_run() {
+    // run a submission on custom input without creating a job
+    // 1. check stdout, stderr and verdicts
+    // 2. check that the job list is still empty
+    // 3. compilation errors come with the compiler messages, also when cached
+    // 4. runs report their peak memory, and the time until killed when time limit exceeded
+    let res = TestCase::read("ext_01_custom_run").run();
+    for i in [6, 10] {
+        assert!(res[i]["stderr"].as_str().unwrap().contains("error"));
+    }
+    assert!(res[1]["memory"].as_u64().unwrap() > 0);
+    assert!(res[11]["memory"].as_u64().unwrap() >= 64 << 20);
+    assert!(res[5]["time"].as_u64().unwrap() >= 2_000_000);
+}
+
+#[test]
+fn test_ext_02_sample_cases() {
+    // check that sample cases are not scored and report their outputs
+    TestCase::read("ext_02_sample_cases").run();
+}
+
+#[test]
+fn test_ext_03_job_cancel() {
+    // cancel a queueing job and a running job
+    // 1. canceled jobs can't be canceled again
+    // 2. the queue goes on after cancellation
+    // 3. canceled jobs don't count towards the submission limit and the ranklist
+    // 4. a job canceled while judged and rejudged right away is judged in full once
+    let res = TestCase::read("ext_03_job_cancel").run();
+    assert_eq!(res[19].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_ext_04_bulk_rejudge() {
+    // rejudge all jobs matching a filter, then poll each of them
+    TestCase::read("ext_04_bulk_rejudge").run();
+}
+
+#[test]
+fn test_ext_05_rejudge_history() {
+    // check that every judgement is kept in the job history
+    // and that a bulk rejudge reports the jobs it changed
+    TestCase::read("ext_05_rejudge_history").run();
+}
+
+#[test]
+fn test_ext_06_job_paging() {
+    // sort, page and trim the job list
+    TestCase::read("ext_06_job_paging").run();
+}
+
+#[test]
+fn test_ext_07_job_filters() {
+    // filter jobs by ranges and lists, and reject malformed filters
+    TestCase::read("ext_07_job_filters").run();
+}
+
+#[test]
+fn test_ext_08_error_responses() {
+    // malformed bodies and paths get the same json errors as every other failure
+    TestCase::read("ext_08_error_responses").run();
+}
+
+/// Accept `count` webhook requests on `address`, answering the first `failures` with 500.
+/// return the signature header and body of each request
+fn webhook_stand_in(
+    address: &str,
+    count: usize,
+    failures: usize,
+) -> thread::JoinHandle<Vec<(String, String)>> {
+    let listener = TcpListener::bind(address).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut requests = Vec::new();
+        while requests.len() < count && Instant::now() < deadline {
+            let mut stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            stream.set_nonblocking(false).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            let mut signature = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                match name.to_lowercase().as_str() {
+                    "content-length" => length = value.trim().parse().unwrap(),
+                    "x-signature" => signature = value.trim().to_string(),
+                    _ => {}
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let status = if requests.len() < failures {
+                "500 Internal Server Error"
+            } else {
+                "200 OK"
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            requests.push((signature, String::from_utf8(body).unwrap()));
+        }
+        requests
+    })
+}
+
+/// Log in as root with the password "rootpw", if the judge answers yet
+fn root_token() -> Option<String> {
+    let res: Value = reqwest::blocking::Client::new()
+        .post("http://127.0.0.1:12345/login")
+        .json(&serde_json::json!({"name": "root", "password": "rootpw"}))
+        .send()
+        .ok()?
+        .json()
+        .ok()?;
+    res["token"].as_str().map(|x| x.to_string())
+}
+
+/// Read the server-sent events of `path` from the judge in the background as root,
+/// retrying until it answers.
+/// Returns the data of each event, and whether the judge ended the stream before `last` matched one.
+fn event_reader(path: &str, last: fn(&Value) -> bool) -> thread::JoinHandle<(Vec<Value>, bool)> {
+    let path = path.to_string();
+    thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut token = String::new();
+        while Instant::now() < deadline {
+            // Until the judge answers with 200 nothing counts, not even errors:
+            // the server of the previous test may still be shutting down
+            if token.is_empty() {
+                token = root_token().unwrap_or_default();
+            }
+            let started = TcpStream::connect("127.0.0.1:12345").and_then(|mut stream| {
+                stream
+                    .set_read_timeout(Some(deadline.saturating_duration_since(Instant::now())))?;
+                write!(
+                    stream,
+                    "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nAuthorization: Bearer {}\r\nConnection: close\r\n\r\n",
+                    path, token
+                )?;
+                let mut reader = BufReader::new(stream);
+                let mut status = String::new();
+                reader.read_line(&mut status)?;
+                Ok((reader, status))
+            });
+            let mut reader = match started {
+                Ok((reader, status)) if status.contains(" 200 ") => reader,
+                // logged in to the previous server
+                Ok((_, status)) if status.contains(" 401 ") => {
+                    token.clear();
+                    continue;
+                }
+                _ => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            let mut events = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return (events, true);
+                }
+                // each event is sent in a chunk of its own, so lines are never split
+                if let Some(data) = line.trim_end().strip_prefix("data: ") {
+                    events.push(serde_json::from_str(data).unwrap());
+                    if last(events.last().unwrap()) {
+                        return (events, false);
+                    }
+                }
+            }
+        }
+        panic!("{} never answered", path);
+    })
+}
+
+#[test]
+fn test_ext_09_webhooks() {
+    // a finished job is posted to the webhook, and retried after a failed delivery
+    let stand_in = webhook_stand_in("127.0.0.1:12346", 2, 1);
+    // retried past 64 times without overflowing the backoff
+    let persistent = webhook_stand_in("127.0.0.1:12347", 66, 65);
+    TestCase::read("ext_09_webhooks").run();
+    assert_eq!(persistent.join().unwrap().len(), 66);
+    let requests = stand_in.join().unwrap();
+    assert_eq!(requests.len(), 2, "webhook not retried");
+    assert_eq!(requests[0], requests[1]);
+    let (signature, body) = &requests[1];
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"key").unwrap();
+    mac.update(body.as_bytes());
+    assert_eq!(
+        *signature,
+        format!("sha256={:x}", mac.finalize().into_bytes())
+    );
+    let body: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body["event"], "job_finished");
+    assert_eq!(body["job"]["id"], 0);
+    assert_eq!(body["job"]["result"], "Accepted");
+    assert!(body["job"].get("source_code").is_none());
+}
+
+#[test]
+fn test_ext_10_auth() {
+    // log in with a password and act as the token's user
+    TestCase::read("ext_10_auth").run();
+}
+
+#[test]
+fn test_ext_11_roles() {
+    // admins manage, observers only read, contestants only read their own code,
+    // and only setters read hidden test data
+    let res = TestCase::read("ext_11_roles").run();
+    assert!(res[11]["submission"].get("source_code").is_none());
+    assert!(res[18][0].get("input").is_none());
+    assert!(res[19][0].get("input").is_some());
+}
+
+#[test]
+fn test_ext_12_users() {
+    // profiles, csv import, name search and soft deletion
+    let res = TestCase::read("ext_12_users").run();
+    assert_eq!(res[6].as_array().unwrap().len(), 1);
+    assert_eq!(res[14].as_array().unwrap().len(), 3);
+    // only the user itself and admins read the private fields
+    assert!(res[15][1].get("email").is_none());
+    assert!(res[15][2].get("disabled").is_none());
+    for field in ["email", "role", "disabled"] {
+        assert!(res[20].get(field).is_none());
+        assert!(res[21][0]["user"].get(field).is_none());
+    }
+}
+
+#[test]
+fn test_ext_13_user_stats() {
+    // stats follow new jobs instead of staying cached
+    let res = TestCase::read("ext_13_user_stats").run();
+    assert_eq!(res[6]["solved"].as_array().unwrap().len(), 1);
+    assert_eq!(res[6]["attempted"].as_array().unwrap().len(), 1);
+    assert!(res[7]["solved"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_ext_14_teams() {
+    // team contests register teams and rank one row per team
+    let res = TestCase::read("ext_14_teams").run();
+    let ranklist = res[17].as_array().unwrap();
+    assert_eq!(ranklist.len(), 2);
+    assert!(ranklist[0].get("user").is_none());
+    // members join by themselves and stay while the contest runs
+    assert!(res[26].get("invited_ids").is_none());
+    assert_eq!(res[31]["member_ids"].as_array().unwrap().len(), 2);
+    // accepting an invitation keeps everyone else on the team
+    assert_eq!(res[33]["member_ids"].as_array().unwrap().len(), 2);
+    assert!(res[33].get("invited_ids").is_none());
+}
+
+#[test]
+fn test_ext_15_registration() {
+    // invite codes, approval, closed contests, registration windows and kicking.
+    // contests are closed unless told otherwise, and only admins see who is waiting
+    let res = TestCase::read("ext_15_registration").run();
+    assert!(res[8].get("invite_code").is_none());
+    for i in [17, 30, 31] {
+        assert!(res[i].get("pending_ids").is_none());
+        assert!(res[i].get("revealed_ids").is_none());
+    }
+    assert!(res[19]["pending_ids"].as_array().unwrap().is_empty());
+    assert!(res[25]["user_ids"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_ext_16_icpc() {
+    // solve times are minutes since the contest started, so check the penalty by relation
+    let res = TestCase::read("ext_16_icpc").run();
+    let time = |row: &Value, cell: usize| row["cells"][cell]["time"].as_i64().unwrap();
+    let first = &res[10][0];
+    assert_eq!(first["penalty"], time(first, 0) + time(first, 1));
+    let second = &res[10][1];
+    assert_eq!(second["penalty"], time(second, 0) + 20);
+    assert!(second["cells"][1].get("time").is_none());
+    let second = &res[12][1];
+    assert_eq!(second["penalty"], time(second, 0) + 5);
+}
+
+#[test]
+fn test_ext_17_ioi_subtasks() {
+    // two jobs solving different subtasks add up under ioi_subtask_max
+    TestCase::read("ext_17_ioi_subtasks").run();
+}
+
+#[test]
+fn test_ext_18_freeze() {
+    // every job is after the freeze, so results only show as they are revealed
+    // the live board needs an admin token even though tokens are optional
+    let res = TestCase::read("ext_18_freeze").run();
+    // nor do the jobs themselves without an admin token
+    assert!(res[12].as_array().unwrap().is_empty());
+    assert!(res[13].get("result").is_none());
+    assert!(res[13].get("cases").is_none());
+    assert_eq!(res[14].as_array().unwrap().len(), 3);
+    assert!(res[20][0].get("pending").is_none());
+    assert!(res[20][1].get("pending").is_none());
+    // hidden test data too
+    assert!(res[22][0].get("input").is_none());
+    assert_eq!(res[23].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn test_ext_19_contest_lifecycle() {
+    // resuming makes up for the pause, ending early moves the end to now
+    let res = TestCase::read("ext_19_contest_lifecycle").run();
+    assert!(res[16]["to"].as_str().unwrap() > "2099-01-01T00:00:00.000Z");
+    assert!(res[16].get("paused_at").is_none());
+    assert!(res[21]["to"].as_str().unwrap() < "2099-01-01T00:00:00.000Z");
+}
+
+#[test]
+fn test_ext_20_contest_problems() {
+    let res = TestCase::read("ext_20_contest_problems").run();
+    assert_eq!(res[10][0]["scores"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_ext_21_allowed_languages() {
+    let res = TestCase::read("ext_21_allowed_languages").run();
+    assert!(res[5][0].get("allowed_languages").is_none());
+    assert!(res[5][0].get("cases").unwrap().is_number());
+}
+
+#[test]
+fn test_ext_22_clarifications() {
+    // private questions are left out for everyone but the asker and admins
+    let res = TestCase::read("ext_22_clarifications").run();
+    assert_eq!(res[19].as_array().unwrap().len(), 3);
+    assert_eq!(res[20].as_array().unwrap().len(), 2);
+    assert_eq!(res[21].as_array().unwrap().len(), 2);
+    assert_eq!(res[24].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn test_ext_23_virtual_contest() {
+    // virtual runs and upsolving stay out of the official ranklist
+    let res = TestCase::read("ext_23_virtual_contest").run();
+    assert_eq!(res[16].as_array().unwrap().len(), 1);
+    assert!(res[16][0].get("virtual").is_none());
+    assert_eq!(res[17].as_array().unwrap().len(), 2);
+    assert_eq!(res[19].as_array().unwrap().len(), 1);
+    assert_eq!(res[23].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_ext_24_dynamic_ranking() {
+    // the shortest times are taken among accepted jobs of the same problem,
+    // so the fast job of problem 1 doesn't lower the score of the slow one of problem 0
+    TestCase::read("ext_24_dynamic_ranking").run();
+}
+
+#[test]
+fn test_ext_25_binary_cache() {
+    // an identical resubmission and a rejudge reuse the first compilation,
+    // so they report its compile time to the microsecond
+    let res = TestCase::read("ext_25_binary_cache").run();
+    let compile_time = &res[0]["cases"][0]["time"];
+    assert!(compile_time.as_u64().unwrap() > 0);
+    assert_eq!(&res[1]["cases"][0]["time"], compile_time);
+    assert_eq!(&res[2]["cases"][0]["time"], compile_time);
+}
+
+#[test]
+fn test_ext_26_event_streams() {
+    // the job stream starts with the job, follows each case and ends once it is finished,
+    // while the contest stream sends updates without the source code
+    let job = event_reader("/jobs/0/events", |_| false);
+    let contest = event_reader("/contests/0/events", |x| x["state"] == "Finished");
+    TestCase::read("ext_26_event_streams").run();
+
+    let (events, ended) = job.join().unwrap();
+    assert!(ended, "job stream not closed");
+    assert!(events.len() > 2, "no progress events");
+    assert_eq!(events[0]["id"], 0);
+    assert_ne!(events[0]["state"], "Finished");
+    assert!(events[1..events.len() - 1]
+        .iter()
+        .any(|x| x["state"] == "Running" && x["cases"][1]["result"] != "Waiting"));
+    let finished = events.last().unwrap();
+    assert_eq!(finished["state"], "Finished");
+    assert_eq!(finished["result"], "Accepted");
+
+    let (events, _) = contest.join().unwrap();
+    let finished = events.last().unwrap();
+    assert_eq!(finished["id"], 0);
+    assert_eq!(finished["result"], "Accepted");
+    assert!(finished.get("submission").is_none());
+}
+
+#[test]
+fn test_ext_27_anonymous_clarifications() {
+    // without a token nobody reads clarifications, even though tokens are optional
+    TestCase::read("ext_27_anonymous_clarifications").run();
+}
+
+#[test]
+fn test_ext_28_tokenless_access() {
+    // while tokens are optional only the baseline apis work without one,
+    // and they can't grant roles or disable users
+    TestCase::read("ext_28_tokenless_access").run();
+}